@@ -182,11 +182,47 @@ pub fn init(_attr: TokenStream, item: TokenStream) -> TokenStream {
     .into()
 }
 
+/// The type argument of `ty` if it is the generic type `wrapper`, like `T` for `Vec<T>`
+fn inner_type<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != wrapper {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        syn::GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    }
+}
+
 // FIXME: Needs to be split into a separate crate
+/// Generates a `<Struct>Args` struct usable with `#[command(flatten)]`, where every field is
+/// optional, and a `merge_opt` function to override the fields of the struct with it.
+///
+/// Doc comments of the fields are used as the help text of the arguments. Depending on the type of
+/// the field the argument works as follows:
+/// - `Vec<T>`: Repeatable, replacing the whole list
+/// - `Option<T>`: Accepts `none` to override the value with `None`
+/// - Anything else: Parsed with `clap::value_parser!`
+///
+/// Struct attributes:
+/// - `#[config_args(pub)]`: Make the generated struct and function public
+/// - `#[config_args(prefix = "name")]`: Prefix the arguments with `--name-`, for use with `nested`
+///
+/// Field attributes:
+/// - `#[config_args(skip)]`: Leave the field out entirely
+/// - `#[config_args(no_arg)]`: Allow overriding the field, but not from the command line
+/// - `#[config_args(nested)]`: The type of the field also derives `ConfigArgs`, its arguments are flattened
 #[proc_macro_derive(ConfigArgs, attributes(config_args))]
 pub fn config_args(item: TokenStream) -> TokenStream {
     let item = parse_macro_input!(item as syn::DeriveInput);
     let mut public = false;
+    let mut prefix = None;
     for attr in &item.attrs {
         if attr.path().is_ident("config_args") {
             attr.parse_nested_meta(|meta| {
@@ -194,6 +230,10 @@ pub fn config_args(item: TokenStream) -> TokenStream {
                     public = true;
                     return Ok(());
                 }
+                if meta.path.is_ident("prefix") {
+                    prefix = Some(meta.value()?.parse::<syn::LitStr>()?.value());
+                    return Ok(());
+                }
 
                 Err(meta.error("Unrecognized macro input"))
             })
@@ -207,12 +247,16 @@ pub fn config_args(item: TokenStream) -> TokenStream {
     };
 
     let opt_ident = Ident::new(&format!("{}Args", item.ident), Span::call_site().into());
+    let deserialize_some = format!("{opt_ident}::deserialize_some");
 
     let mut operations = quote!();
     let mut fields = quote!();
 
     for field in data.fields.iter() {
         let mut skip = false;
+        // Fields that can be overridden, but not from the command line
+        let mut no_arg = false;
+        let mut nested = false;
         for attr in &field.attrs {
             if attr.path().is_ident("config_args") {
                 attr.parse_nested_meta(|meta| {
@@ -220,6 +264,14 @@ pub fn config_args(item: TokenStream) -> TokenStream {
                         skip = true;
                         return Ok(());
                     }
+                    if meta.path.is_ident("no_arg") {
+                        no_arg = true;
+                        return Ok(());
+                    }
+                    if meta.path.is_ident("nested") {
+                        nested = true;
+                        return Ok(());
+                    }
 
                     Err(meta.error("Unrecognized macro input"))
                 })
@@ -231,6 +283,46 @@ pub fn config_args(item: TokenStream) -> TokenStream {
         }
         let ty = &field.ty;
         let ident = field.ident.as_ref().unwrap();
+        let docs = field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("doc"));
+
+        let name = ident.to_string().replace('_', "-");
+        let (id, long) = match &prefix {
+            Some(prefix) => {
+                let name = format!("{prefix}-{name}");
+                let value_name = name.to_uppercase().replace('-', "_");
+                (
+                    quote!(id = #name, value_name = #value_name,),
+                    quote!(long = #name),
+                )
+            }
+            None => (quote!(), quote!(long)),
+        };
+
+        if nested {
+            let Type::Path(path) = ty else {
+                panic!("Nested fields must be structs deriving ConfigArgs");
+            };
+            let nested_ident = Ident::new(
+                &format!("{}Args", path.path.segments.last().unwrap().ident),
+                Span::call_site().into(),
+            );
+
+            operations = quote! {
+                #operations
+                self.#ident.merge_opt(opt.#ident);
+            };
+
+            fields = quote! {
+                #fields
+                #[command(flatten)]
+                #[serde(default)]
+                #ident: #nested_ident,
+            };
+            continue;
+        }
 
         operations = quote! {
             #operations
@@ -239,10 +331,75 @@ pub fn config_args(item: TokenStream) -> TokenStream {
             }
         };
 
-        fields = quote! {
-            #fields
-            #[arg(long)]
-            #ident: Option<#ty>,
+        fields = if no_arg {
+            quote! {
+                #fields
+                #[arg(skip)]
+                #[serde(skip_serializing, default)]
+                #ident: Option<#ty>,
+            }
+        } else if inner_type(ty, "Vec").is_some() {
+            quote! {
+                #fields
+                #(#docs)*
+                #[arg(#id #long, action = ::clap::ArgAction::Append)]
+                #ident: Option<#ty>,
+            }
+        } else if let Some(inner) = inner_type(ty, "Option") {
+            // The inner `Option` is written out in full so that clap doesn't treat it specially,
+            // the argument then always takes a value which is either `none` or the inner value.
+            quote! {
+                #fields
+                #(#docs)*
+                #[arg(
+                    #id #long,
+                    value_parser = |value: &str| -> ::core::result::Result<
+                        ::core::option::Option<#inner>,
+                        ::std::string::String,
+                    > {
+                        if value == "none" {
+                            return ::core::result::Result::Ok(::core::option::Option::None);
+                        }
+
+                        ::clap::Command::new("")
+                            .arg(
+                                ::clap::Arg::new("value")
+                                    .allow_hyphen_values(true)
+                                    .value_parser(::clap::value_parser!(#inner)),
+                            )
+                            .try_get_matches_from(["", value])
+                            .map(|mut matches| matches.remove_one::<#inner>("value"))
+                            .map_err(|why| {
+                                match (
+                                    ::std::error::Error::source(&why),
+                                    why.get(::clap::error::ContextKind::ValidValue),
+                                ) {
+                                    (::core::option::Option::Some(source), _) => source.to_string(),
+                                    (
+                                        ::core::option::Option::None,
+                                        ::core::option::Option::Some(
+                                            ::clap::error::ContextValue::Strings(values),
+                                        ),
+                                    ) => ::std::format!("possible values: none, {}", values.join(", ")),
+                                    _ => why.kind().to_string(),
+                                }
+                            })
+                    },
+                )]
+                #[serde(
+                    default,
+                    skip_serializing_if = "Option::is_none",
+                    deserialize_with = #deserialize_some,
+                )]
+                #ident: Option<::core::option::Option<#inner>>,
+            }
+        } else {
+            quote! {
+                #fields
+                #(#docs)*
+                #[arg(#id #long)]
+                #ident: Option<#ty>,
+            }
         };
     }
 
@@ -253,11 +410,31 @@ pub fn config_args(item: TokenStream) -> TokenStream {
     };
 
     quote! {
-        #[derive(::clap::Args, ::std::clone::Clone, ::std::fmt::Debug, ::serde::Serialize, ::serde::Deserialize)]
+        #[derive(
+            ::clap::Args,
+            ::std::clone::Clone,
+            ::std::fmt::Debug,
+            ::std::default::Default,
+            ::serde::Serialize,
+            ::serde::Deserialize,
+        )]
         #struct_decl {
             #fields
         }
 
+        impl #opt_ident {
+            /// Deserialize a present value as `Some`, so that an explicit `None` differs from a
+            /// missing value for `Option` fields
+            #[allow(dead_code)]
+            fn deserialize_some<'de, D, T>(deserializer: D) -> ::core::result::Result<Option<T>, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+                T: ::serde::Deserialize<'de>,
+            {
+                T::deserialize(deserializer).map(::core::option::Option::Some)
+            }
+        }
+
         impl #ident {
             #fn_decl(&mut self, opt: #opt_ident) {
                 #operations
@@ -1,31 +1,191 @@
 use crate::{
-    config::{self, Action, Config, Keybind},
+    config::{self, Action, Config, ConfigError, Keybind, Layout, Position},
+    history::History,
     plugin_box::{PluginBox, PluginBoxInput, PluginBoxOutput, PluginMatch},
-    provider, Args, DaemonState,
+    provider, Args, DaemonState, Invocation, OutputFormat,
 };
-use anyrun_interface::HandleResult;
+use abi_stable::std_types::RVec;
+use anyrun_interface::{HandleResult, Match, PluginInfo};
 use anyrun_provider_ipc as ipc;
-use gtk::{gdk, gio, glib, prelude::*};
+use gtk::{gdk, gio, glib, pango, prelude::*};
 use gtk4 as gtk;
 use gtk4_layer_shell::{Edge, LayerShell};
 use relm4::{prelude::*, ComponentBuilder, Sender};
 use serde::{Deserialize, Serialize};
 use std::{
     cell::RefCell,
-    env, fs,
+    collections::VecDeque,
     io::{self, Write},
-    path::{Path, PathBuf},
+    path::Path,
     rc::Rc,
     sync::Arc,
+    time::Duration,
 };
 use tokio::sync::mpsc;
 
 const DEFAULT_CSS: &str = include_str!("../res/style.css");
 
-#[derive(Deserialize, Serialize)]
+/// The amount of matches `Action::PageUp` and `Action::PageDown` move the selection by
+const PAGE_SIZE: usize = 10;
+
+/// The time in milliseconds after which a plugin that has not answered is shown as loading. Shorter
+/// waits are not shown, so that fast plugins don't flicker.
+const SLOW_PLUGIN_DELAY: u64 = 200;
+
+/// Find the `gdk::Monitor` the window should be shown on, `None` leaves the choice to the compositor
+fn find_monitor(target: &config::Monitor, display: &gdk::Display) -> Option<gdk::Monitor> {
+    let monitors = display.monitors();
+    let monitor = match target {
+        config::Monitor::Focused => return None,
+        config::Monitor::Index(i) => monitors.item(*i).and_downcast(),
+        config::Monitor::Connector(connector) => (0..monitors.n_items())
+            .filter_map(|i| monitors.item(i).and_downcast::<gdk::Monitor>())
+            .find(|monitor| monitor.connector().as_deref() == Some(connector.as_str())),
+    };
+
+    if monitor.is_none() {
+        eprintln!("[anyrun] Monitor {target:?} not found, letting the compositor choose");
+    }
+    monitor
+}
+
+/// Load `style.css` from the config directory, falling back to the default style if it doesn't exist
+fn load_style(css_provider: &gtk::CssProvider, config_dir: &str) {
+    let css_path = Path::new(config_dir).join("style.css");
+    if css_path.exists() {
+        css_provider.load_from_path(css_path);
+    } else {
+        eprintln!("[anyrun] {config_dir}/style.css does not exist");
+        css_provider.load_from_string(DEFAULT_CSS);
+    }
+}
+
+/// Describe an error in `style.css` along with its location
+pub fn style_error(section: &gtk::CssSection, why: &glib::Error) -> String {
+    let location = section.start_location();
+    format!(
+        "Failed to parse stylesheet at style.css:{}:{}: {why}",
+        location.lines() + 1,
+        location.line_chars() + 1
+    )
+}
+
+/// The index of the first match in the run of consecutive matches from the same plugin as the match at `i`
+fn group_start(matches: &[(&PluginBox, &PluginMatch)], i: usize) -> usize {
+    let (plugin, _) = matches[i];
+    matches[..i]
+        .iter()
+        .rposition(|(other, _)| !std::ptr::eq(*other, plugin))
+        .map_or(0, |j| j + 1)
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub enum PostRunAction {
     Stdout(Vec<u8>),
     None,
+    /// Closed without selecting anything
+    Cancelled,
+}
+
+/// The match that was selected
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Selected {
+    pub plugin: String,
+    pub title: String,
+    pub description: Option<String>,
+    pub id: Option<u64>,
+}
+
+impl Selected {
+    fn new(plugin: &PluginInfo, content: &Match) -> Self {
+        Self {
+            plugin: plugin.name.to_string(),
+            title: content.title.to_string(),
+            description: content
+                .description
+                .as_ref()
+                .map(|desc| desc.to_string())
+                .into(),
+            id: content.id.into(),
+        }
+    }
+}
+
+/// The outcome of showing the launcher, returned by the daemon when it is closed
+#[derive(Deserialize, Serialize)]
+pub struct RunResult {
+    pub action: PostRunAction,
+    /// The last match that was selected
+    pub selection: Option<Selected>,
+    /// The input when the launcher was closed
+    pub query: String,
+}
+
+impl RunResult {
+    pub fn cancelled(&self) -> bool {
+        matches!(self.action, PostRunAction::Cancelled)
+    }
+
+    /// The action as returned by the `Show` method, which older clients don't know `Cancelled` in
+    pub fn legacy_action(&self) -> PostRunAction {
+        match &self.action {
+            PostRunAction::Cancelled => PostRunAction::None,
+            action => action.clone(),
+        }
+    }
+
+    /// The result as the output arguments of the typed `Open` method. The selection strings are
+    /// empty and the id is 0 if nothing was selected.
+    pub fn to_variant(&self) -> glib::Variant {
+        let selection = self.selection.as_ref().filter(|_| !self.cancelled());
+        let output = match &self.action {
+            PostRunAction::Stdout(bytes) => bytes.clone(),
+            PostRunAction::None | PostRunAction::Cancelled => Vec::new(),
+        };
+        (
+            self.cancelled(),
+            self.query.clone(),
+            output,
+            selection
+                .map(|selection| selection.plugin.clone())
+                .unwrap_or_default(),
+            selection
+                .map(|selection| selection.title.clone())
+                .unwrap_or_default(),
+            selection
+                .and_then(|selection| selection.description.clone())
+                .unwrap_or_default(),
+            selection
+                .and_then(|selection| selection.id)
+                .unwrap_or_default(),
+        )
+            .to_variant()
+    }
+
+    /// Print the result to stdout in the format requested with `--output`
+    pub fn print(&self, format: OutputFormat) {
+        match format {
+            OutputFormat::Plain => {
+                if let PostRunAction::Stdout(bytes) = &self.action {
+                    io::stdout().lock().write_all(bytes).unwrap();
+                }
+            }
+            OutputFormat::Json => {
+                let selection = self.selection.as_ref().filter(|_| !self.cancelled());
+                println!(
+                    "{}",
+                    serde_json::json!({
+                        "plugin": selection.map(|selection| &selection.plugin),
+                        "title": selection.map(|selection| &selection.title),
+                        "description": selection.and_then(|selection| selection.description.as_ref()),
+                        "id": selection.and_then(|selection| selection.id),
+                        "query": self.query,
+                        "cancelled": self.cancelled(),
+                    })
+                );
+            }
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -45,7 +205,36 @@ pub enum AppMsg {
     },
     Action(Action),
     EntryChanged(String),
+    /// Debounced query, discarded if the input has changed since
+    Query {
+        seq: u64,
+        text: String,
+    },
+    /// Sent when a plugin has been answering the query `seq` for `SLOW_PLUGIN_DELAY`
+    PluginSlow {
+        plugin: usize,
+        seq: u64,
+    },
+    /// Sent when the timeout of a plugin for the query `seq` has elapsed
+    PluginTimeout {
+        plugin: usize,
+        seq: u64,
+    },
     PluginOutput(PluginBoxOutput),
+    /// A file in the config directory has changed, only sent in daemon mode
+    ConfigFileChanged(String),
+    /// The stylesheet failed to parse at a location
+    StyleError(String),
+    /// Replace the input, sent through the DBus interface
+    SetQuery(String),
+    /// Perform an action sent through the `Action` DBus method, answering it with an error if the
+    /// action can't do anything
+    RemoteAction {
+        action: Action,
+        invocation: gio::DBusMethodInvocation,
+    },
+    /// Answer the `GetState` DBus method with the input and the visible matches
+    GetState(gio::DBusMethodInvocation),
 }
 
 #[derive(Deserialize, Serialize)]
@@ -57,18 +246,71 @@ pub struct AppInit {
 
 pub struct App {
     config: Arc<Config>,
-    daemon_ctx: Option<(Rc<RefCell<DaemonState>>, gio::DBusMethodInvocation)>,
+    daemon_ctx: Option<(Rc<RefCell<DaemonState>>, Invocation)>,
     plugins: FactoryVecDeque<PluginBox>,
+    /// The matches of all plugins sorted by their score, used with `Layout::Merged`
+    merged: FactoryVecDeque<PluginMatch>,
+    /// The index of the plugin each of the merged matches originates from
+    merged_sources: Vec<usize>,
     post_run_action: PostRunAction,
+    history: History,
+    /// The position in the query history while it is being navigated
+    query_index: Option<usize>,
+    /// Plugins shown as loading or timed out in the status of `Layout::Merged`, where the plugin
+    /// boxes are hidden
+    slow_plugins: Vec<usize>,
+    timed_out_plugins: Vec<usize>,
+    /// The input before navigating the query history
+    query_draft: String,
+    /// Incremented on every input change, to discard debounced queries for outdated inputs
+    input_seq: u64,
+    /// The amount of queries sent to the provider, to tell apart the timers of different queries
+    query_seq: u64,
+    /// The amount of queries each plugin has not answered yet, more than one are left by timeouts
+    unanswered: Vec<usize>,
+    /// Whether each plugin is still expected to answer the last query
+    waiting: Vec<bool>,
+    /// The latest input, held back until no plugin is expected to answer the last query
+    queued_query: Option<String>,
     tx: mpsc::Sender<anyrun_provider_ipc::Request>,
     css_provider: gtk::CssProvider,
+    config_dir: Option<String>,
+    /// The text the input is filled with when shown
+    initial_query: Option<String>,
+    /// Whether the initial query is selected
+    select_all: bool,
+    /// Whether running as a dmenu replacement
+    dmenu: bool,
+    /// Whether the input is masked
+    password: bool,
+    /// Matches marked for `Action::SelectMarked`, kept while the input changes
+    marked: Vec<(PluginInfo, Match)>,
+    /// Whether the window has been shown, `AppMsg::Show` can be sent more than once
+    shown: bool,
+    /// The row of the match the action menu was opened for, it is shown while that row is selected
+    action_menu: Option<gtk::ListBoxRow>,
+    /// The amount of selections sent to be handled that have not been answered yet
+    unhandled: usize,
+    /// The marked matches sent to be handled that have not been handled yet, in order
+    pending_marked: VecDeque<(PluginInfo, Match)>,
+    /// The output of the marked matches handled so far
+    marked_output: Vec<Vec<u8>>,
+    /// The last match sent to be handled
+    selection: Option<Selected>,
+    output: OutputFormat,
+    /// Watches the config directory for changes in daemon mode
+    config_monitor: Option<gio::FileMonitor>,
+    /// The error from parsing `config.ron`, shown in a banner
+    config_error: Option<String>,
+    /// The errors from parsing `style.css`, shown in a banner
+    style_errors: Vec<String>,
 }
 
 impl App {
     pub fn launch(
         app: &gtk::Application,
         app_init: AppInit,
-        daemon_ctx: Option<(Rc<RefCell<DaemonState>>, gio::DBusMethodInvocation)>,
+        daemon_ctx: Option<(Rc<RefCell<DaemonState>>, Invocation)>,
     ) -> Sender<AppMsg> {
         let builder = ComponentBuilder::<App>::default();
 
@@ -85,43 +327,228 @@ impl App {
 
     /// Helper function to get the combined matches of all the plugins
     fn combined_matches(&self) -> Vec<(&PluginBox, &PluginMatch)> {
-        self.plugins
-            .iter()
-            .flat_map(|plugin| {
-                plugin
-                    .matches
-                    .iter()
-                    .map(|plugin_match| (plugin, plugin_match))
-                    .collect::<Vec<_>>()
+        match self.config.layout {
+            Layout::Grouped => self
+                .plugins
+                .iter()
+                .flat_map(|plugin| {
+                    plugin
+                        .matches
+                        .iter()
+                        .map(|plugin_match| (plugin, plugin_match))
+                        .collect::<Vec<_>>()
+                })
+                .collect(),
+            Layout::Merged => self
+                .merged
+                .iter()
+                .zip(&self.merged_sources)
+                .map(|(plugin_match, i)| (self.plugins.get(*i).unwrap(), plugin_match))
+                .collect(),
+        }
+    }
+
+    fn current_selection(&self) -> Option<(usize, &PluginBox, &PluginMatch)> {
+        self.combined_matches()
+            .into_iter()
+            .enumerate()
+            .find(|(_, (_, plugin_match))| plugin_match.row.is_selected())
+            .map(|(i, (plugin, plugin_match))| (i, plugin, plugin_match))
+    }
+
+    /// Emit a lifecycle signal on the DBus interface, only done in daemon mode
+    fn emit_signal(&self, name: &str, parameters: Option<&glib::Variant>) {
+        if let Some((daemon_state, _)) = &self.daemon_ctx {
+            daemon_state.borrow().emit_signal(name, parameters);
+        }
+    }
+
+    /// The position of a match in the marked matches, if it is marked
+    fn mark_position(&self, plugin: &PluginBox, plugin_match: &PluginMatch) -> Option<usize> {
+        self.marked.iter().position(|(info, content)| {
+            info.name == plugin.plugin_info.name
+                && content.title == plugin_match.content.title
+                && content.id == plugin_match.content.id
+        })
+    }
+
+    /// Stable sort the matches of a plugin by their frecency
+    fn rank_by_frecency(&self, plugin: &str, matches: RVec<Match>, input: &str) -> RVec<Match> {
+        let mut matches = matches
+            .into_iter()
+            .map(|plugin_match| {
+                (
+                    self.history.frecency(
+                        plugin,
+                        &plugin_match.title,
+                        input,
+                        self.config.frecency_half_life,
+                    ),
+                    plugin_match,
+                )
             })
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| b.0.total_cmp(&a.0));
+        matches
+            .into_iter()
+            .map(|(_, plugin_match)| plugin_match)
             .collect()
     }
 
-    fn current_selection(&self) -> Option<(usize, &PluginBox, &PluginMatch)> {
-        self.plugins
+    /// Send a query for the input to the provider. Its answers don't tell which query they belong
+    /// to, so only one query is in flight at a time and a newer input is queued until every plugin
+    /// has answered or timed out.
+    fn query(&mut self, text: String, sender: &ComponentSender<Self>) {
+        if self.waiting.is_empty() || self.waiting.contains(&true) {
+            self.queued_query = Some(text);
+            return;
+        }
+
+        self.query_seq += 1;
+        let _ = self.tx.blocking_send(ipc::Request::Query { text });
+        for (unanswered, waiting) in self.unanswered.iter_mut().zip(&mut self.waiting) {
+            *unanswered += 1;
+            *waiting = true;
+        }
+
+        self.plugins.broadcast(PluginBoxInput::Loading);
+        for (plugin, plugin_box) in self.plugins.iter().enumerate() {
+            let seq = self.query_seq;
+            glib::timeout_add_local_once(
+                Duration::from_millis(SLOW_PLUGIN_DELAY),
+                glib::clone!(
+                    #[strong]
+                    sender,
+                    move || sender.input(AppMsg::PluginSlow { plugin, seq })
+                ),
+            );
+
+            let timeout = self.config.plugin_timeout(&plugin_box.plugin_info.name);
+            if timeout > 0 {
+                let seq = self.query_seq;
+                glib::timeout_add_local_once(
+                    Duration::from_millis(timeout),
+                    glib::clone!(
+                        #[strong]
+                        sender,
+                        move || sender.input(AppMsg::PluginTimeout { plugin, seq })
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Send the queued input once no plugin is expected to answer the last query
+    fn send_queued_query(&mut self, sender: &ComponentSender<Self>) {
+        if !self.waiting.contains(&true) {
+            if let Some(text) = self.queued_query.take() {
+                self.query(text, sender);
+            }
+        }
+    }
+
+    /// The plugins still loading and the ones that timed out, shown with `Layout::Merged`
+    fn status(&self) -> String {
+        let names = |plugins: &[usize]| {
+            plugins
+                .iter()
+                .filter_map(|&i| self.plugins.get(i))
+                .map(|plugin| plugin.plugin_info.name.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        [
+            Some(&self.slow_plugins)
+                .filter(|plugins| !plugins.is_empty())
+                .map(|plugins| format!("Waiting for {}", names(plugins))),
+            Some(&self.timed_out_plugins)
+                .filter(|plugins| !plugins.is_empty())
+                .map(|plugins| format!("{} did not respond in time", names(plugins))),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+
+    /// Move the selection among the visible matches to the index returned by `target`,
+    /// which receives the index of the current selection and the visible matches
+    fn move_selection(&self, target: impl FnOnce(usize, &[(&PluginBox, &PluginMatch)]) -> usize) {
+        let matches = self
+            .combined_matches()
+            .into_iter()
+            .filter(|(_, plugin_match)| plugin_match.row.get_visible())
+            .collect::<Vec<_>>();
+
+        if let Some(i) = matches
             .iter()
-            .find_map(|plugin| {
-                plugin
-                    .matches
-                    .widget()
-                    .selected_row()
-                    .map(|row| (plugin, row))
-            })
-            .map(|(plugin, row)| {
-                let (i, plugin_match) = self
-                    .combined_matches()
-                    .iter()
-                    .enumerate()
-                    .find_map(|(i, (_, plugin_match))| {
-                        if plugin_match.row == row {
-                            Some((i, *plugin_match))
-                        } else {
-                            None
-                        }
+            .position(|(_, plugin_match)| plugin_match.row.is_selected())
+        {
+            let (plugin, plugin_match) = matches[target(i, &matches)];
+            self.select(plugin, plugin_match);
+        }
+    }
+
+    /// The edges the window is anchored to horizontally and vertically along with the margin
+    /// from them, `None` meaning that the window is centered on that axis
+    #[allow(clippy::type_complexity)]
+    fn anchors(
+        &self,
+        width: i32,
+        height: i32,
+        mon_width: u32,
+        mon_height: u32,
+    ) -> (Option<(Edge, i32)>, Option<(Edge, i32)>) {
+        let x = self.config.x.to_val(mon_width);
+        let y = self.config.y.to_val(mon_height);
+
+        match self.config.position {
+            // `x` and `y` are adjusted so that `Fraction(0.5)` centers the runner
+            Position::Top => (
+                Some((Edge::Left, x - width / 2)),
+                Some((Edge::Top, y - height / 2)),
+            ),
+            Position::Bottom => (Some((Edge::Left, x - width / 2)), Some((Edge::Bottom, y))),
+            Position::Center => (None, None),
+            Position::TopLeft => (Some((Edge::Left, x)), Some((Edge::Top, y))),
+            Position::TopRight => (Some((Edge::Right, x)), Some((Edge::Top, y))),
+            Position::BottomLeft => (Some((Edge::Left, x)), Some((Edge::Bottom, y))),
+            Position::BottomRight => (Some((Edge::Right, x)), Some((Edge::Bottom, y))),
+        }
+    }
+
+    /// Select the row of a match in the list it is displayed in
+    fn select(&self, plugin: &PluginBox, plugin_match: &PluginMatch) {
+        match self.config.layout {
+            Layout::Grouped => plugin.matches.widget().select_row(Some(&plugin_match.row)),
+            Layout::Merged => self.merged.widget().select_row(Some(&plugin_match.row)),
+        }
+    }
+
+    /// Rebuild the merged list from the matches of all the plugins
+    fn merge_matches(&mut self) {
+        let mut matches =
+            self.plugins
+                .iter()
+                .enumerate()
+                .flat_map(|(i, plugin)| {
+                    plugin.matches.iter().map(move |plugin_match| {
+                        (i, plugin_match.score, plugin_match.content.clone())
                     })
-                    .unwrap(); // Unwrap is safe since we just obtained the selected one
-                (i, plugin, plugin_match)
-            })
+                })
+                .collect::<Vec<_>>();
+
+        // The sort is stable, so plugin order is used to break ties
+        matches.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        self.merged_sources = matches.iter().map(|(i, _, _)| *i).collect();
+
+        let mut guard = self.merged.guard();
+        guard.clear();
+        for (_, score, content) in matches {
+            guard.push_back((content, score, self.config.clone()));
+        }
     }
 }
 
@@ -129,10 +556,7 @@ impl App {
 impl Component for App {
     type Input = AppMsg;
     type Output = ();
-    type Init = (
-        AppInit,
-        Option<(Rc<RefCell<DaemonState>>, gio::DBusMethodInvocation)>,
-    );
+    type Init = (AppInit, Option<(Rc<RefCell<DaemonState>>, Invocation)>);
     type CommandOutput = anyrun_provider_ipc::Response;
 
     view! {
@@ -149,6 +573,7 @@ impl Component for App {
                 config::KeyboardMode::OnDemand => gtk4_layer_shell::KeyboardMode::OnDemand,
             },
             set_namespace: Some("anyrun"),
+            set_monitor: monitor.as_ref(),
             // This cannot be fully transparent due to a Sway issue (https://github.com/swaywm/sway/issues/8904)
             // so this ugly workaround is the only way to make it work
             // FIXME: This is dumb
@@ -159,15 +584,24 @@ impl Component for App {
                 0
             },
 
-            connect_realize[sender] => move |win| {
-                let surface = win.surface().unwrap();
-                let sender = sender.clone();
-                surface.connect_enter_monitor(move |_, monitor| {
+            connect_realize[sender, monitor] => move |win| {
+                // The geometry of a configured monitor is known, otherwise wait for the compositor
+                // to place the window on one
+                if let Some(monitor) = &monitor {
                     sender.input(AppMsg::Show {
                         width: monitor.geometry().width() as u32,
                         height: monitor.geometry().height() as u32,
                     });
-                });
+                } else {
+                    let surface = win.surface().unwrap();
+                    let sender = sender.clone();
+                    surface.connect_enter_monitor(move |_, monitor| {
+                        sender.input(AppMsg::Show {
+                            width: monitor.geometry().width() as u32,
+                            height: monitor.geometry().height() as u32,
+                        });
+                    });
+                }
             },
 
             add_controller = gtk::GestureClick {
@@ -186,23 +620,61 @@ impl Component for App {
                 set_hexpand: true,
                 set_css_classes: &["main"],
 
-                #[name = "entry"]
-                gtk::Text {
-                    set_hexpand: true,
-                    set_activates_default: false,
-                    connect_changed[sender] => move |entry| {
-                        sender.input(AppMsg::EntryChanged(entry.text().into()));
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Horizontal,
+                    set_css_classes: &["input"],
+
+                    gtk::Label {
+                        set_css_classes: &["prompt"],
+                        set_visible: app_init.args.prompt.is_some(),
+                        set_label: app_init.args.prompt.as_deref().unwrap_or_default(),
                     },
+                    #[name = "entry"]
+                    gtk::Text {
+                        set_hexpand: true,
+                        set_activates_default: false,
+                        set_placeholder_text: app_init.args.placeholder.as_deref(),
+                        set_visibility: !app_init.args.password,
+                        connect_changed[sender] => move |entry| {
+                            sender.input(AppMsg::EntryChanged(entry.text().into()));
+                        } @entry_changed,
 
-                    add_controller = gtk::EventControllerKey {
-                        connect_key_pressed[sender] => move |_, key, _, modifier| {
-                            sender.input(AppMsg::KeyPressed { key, modifier});
-                            match key {
-                                gdk::Key::Tab => glib::Propagation::Stop,
-                                _ => glib::Propagation::Proceed,
+                        add_controller = gtk::EventControllerKey {
+                            connect_key_pressed[sender] => move |_, key, _, modifier| {
+                                sender.input(AppMsg::KeyPressed { key, modifier});
+                                match key {
+                                    gdk::Key::Tab => glib::Propagation::Stop,
+                                    _ => glib::Propagation::Proceed,
+                                }
                             }
                         }
-                    }
+                    },
+                },
+                gtk::Label {
+                    set_css_classes: &["banner"],
+                    set_wrap: true,
+                    set_xalign: 0.0,
+                    #[watch]
+                    set_visible: model.config_error.is_some() || !model.style_errors.is_empty(),
+                    #[watch]
+                    set_label: &model
+                        .config_error
+                        .iter()
+                        .chain(&model.style_errors)
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                },
+                // Plugins can't provide alternate actions for their matches yet
+                gtk::Label {
+                    set_css_classes: &["actions"],
+                    set_xalign: 0.0,
+                    set_label: "This match has no other actions",
+                    #[watch]
+                    set_visible: model
+                        .action_menu
+                        .as_ref()
+                        .is_some_and(|row| row.is_selected()),
                 },
                 #[local]
                 plugins -> gtk::Box {
@@ -210,6 +682,36 @@ impl Component for App {
                     set_can_focus: false,
                     set_css_classes: &["matches"],
                     set_hexpand: true,
+                    set_visible: config.layout == Layout::Grouped,
+                },
+                gtk::Box {
+                    set_css_classes: &["status"],
+                    set_spacing: 5,
+                    #[watch]
+                    set_visible: config.layout == Layout::Merged
+                        && !(model.slow_plugins.is_empty() && model.timed_out_plugins.is_empty()),
+
+                    gtk::Spinner {
+                        set_css_classes: &["status"],
+                        #[watch]
+                        set_spinning: !model.slow_plugins.is_empty(),
+                        #[watch]
+                        set_visible: !model.slow_plugins.is_empty(),
+                    },
+                    gtk::Label {
+                        set_css_classes: &["status"],
+                        set_wrap: true,
+                        set_xalign: 0.0,
+                        #[watch]
+                        set_label: &model.status(),
+                    }
+                },
+                #[local]
+                merged -> gtk::ListBox {
+                    set_can_focus: false,
+                    set_css_classes: &["plugin", "merged"],
+                    set_hexpand: true,
+                    set_visible: config.layout == Layout::Merged,
                 }
             }
         }
@@ -220,47 +722,29 @@ impl Component for App {
         root: Self::Root,
         sender: relm4::ComponentSender<Self>,
     ) -> relm4::ComponentParts<Self> {
-        let user_dir = env::var("XDG_CONFIG_HOME")
-            .map(|c| format!("{c}/anyrun"))
-            .or_else(|_| env::var("HOME").map(|h| format!("{h}/.config/anyrun")))
-            .unwrap();
-
-        let config_dir = app_init
-            .args
-            .config_dir
-            .clone()
-            .map(Some)
-            .unwrap_or_else(|| {
-                if PathBuf::from(&user_dir).exists() {
-                    Some(user_dir.clone())
-                } else {
-                    ipc::CONFIG_DIRS
-                        .iter()
-                        .map(|path| path.to_string())
-                        .find(|path| PathBuf::from(path).exists())
-                }
-            });
+        let config_dir = config::find_config_dir(app_init.args.config_dir.clone());
 
         let css_provider = gtk::CssProvider::new();
+        css_provider.connect_parsing_error(glib::clone!(
+            #[strong]
+            sender,
+            move |_, section, why| {
+                let error = style_error(section, why);
+                eprintln!("[anyrun] {error}");
+                sender.input(AppMsg::StyleError(error));
+            }
+        ));
 
+        let mut config_error = None;
         let mut config = if let Some(config_dir) = &config_dir {
-            let css_path = Path::new(&config_dir).join("style.css");
-            if css_path.exists() {
-                css_provider.load_from_path(css_path);
-            } else {
-                eprintln!("[anyrun] {config_dir}/style.css does not exist");
-                css_provider.load_from_string(DEFAULT_CSS);
-            };
-            match fs::read(format!("{config_dir}/config.ron")) {
-                Ok(content) => ron::de::from_bytes(&content).unwrap_or_else(|why| {
-                    eprintln!("[anyrun] Failed to parse config file, using default values: {why}");
-                    Config::default()
-                }),
-                Err(why) => {
-                    eprintln!("[anyrun] Failed to read config file, using default values: {why}");
-                    Config::default()
+            load_style(&css_provider, config_dir);
+            Config::load(config_dir).unwrap_or_else(|why| {
+                eprintln!("[anyrun] {why}, using default values");
+                if matches!(why, ConfigError::Parse(_)) {
+                    config_error = Some(why.to_string());
                 }
-            }
+                Config::default()
+            })
         } else {
             eprintln!("[anyrun] No config found in any searched paths");
             css_provider.load_from_string(DEFAULT_CSS);
@@ -273,44 +757,148 @@ impl Component for App {
             gtk::STYLE_PROVIDER_PRIORITY_USER,
         );
 
-        config.merge_opt(app_init.args.config.clone());
+        if !app_init.args.apply_overrides(&mut config) {
+            eprintln!(
+                "[anyrun] Profile `{}` does not exist, using the config as is",
+                app_init.args.profile.as_deref().unwrap_or_default()
+            );
+        }
 
         let config = Arc::new(config);
 
+        let monitor = find_monitor(&config.monitor, &WidgetExt::display(&root));
+
+        // Only the daemon lives long enough for editing the config while it is running to matter
+        let config_monitor = config_dir
+            .as_ref()
+            .filter(|_| daemon_ctx.is_some())
+            .and_then(|config_dir| {
+                gio::File::for_path(config_dir)
+                    .monitor_directory(gio::FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+                    .inspect_err(|why| {
+                        eprintln!("[anyrun] Failed to watch the config directory: {why}")
+                    })
+                    .ok()
+            });
+
+        if let Some(config_monitor) = &config_monitor {
+            config_monitor.connect_changed(glib::clone!(
+                #[strong]
+                sender,
+                move |_, file, other_file, event| {
+                    if matches!(
+                        event,
+                        gio::FileMonitorEvent::ChangesDoneHint
+                            | gio::FileMonitorEvent::Created
+                            | gio::FileMonitorEvent::Deleted
+                            | gio::FileMonitorEvent::Renamed
+                            | gio::FileMonitorEvent::MovedIn
+                            | gio::FileMonitorEvent::MovedOut
+                    ) {
+                        // Editors commonly save by renaming a temporary file over the original
+                        for file in [Some(file), other_file].into_iter().flatten() {
+                            if let Some(name) = file.basename() {
+                                sender.input(AppMsg::ConfigFileChanged(
+                                    name.to_string_lossy().into_owned(),
+                                ));
+                            }
+                        }
+                    }
+                }
+            ));
+        }
+
         let plugins = gtk::Box::builder().build();
 
         let plugins_factory = FactoryVecDeque::<PluginBox>::builder()
             .launch(plugins.clone())
             .forward(sender.input_sender(), AppMsg::PluginOutput);
 
+        let merged = gtk::ListBox::builder().build();
+
+        let merged_factory = FactoryVecDeque::<PluginMatch>::builder()
+            .launch(merged.clone())
+            .detach();
+
         let (tx, rx) = mpsc::channel(10);
 
-        sender.spawn_command(glib::clone!(
-            #[strong]
-            config,
-            #[strong]
-            config_dir,
-            #[strong(rename_to = stdin)]
-            app_init.stdin,
-            #[strong(rename_to = env)]
-            app_init.env,
-            move |sender| {
-                if let Err(why) = provider::worker(config, config_dir, rx, sender, stdin, env) {
-                    eprintln!("[anyrun] IPC worker returned an error: {why}");
+        let session = provider::Session {
+            rx,
+            sender: sender.command_sender().clone(),
+        };
+        match &daemon_ctx {
+            // Input on stdin is only read by plugins when they are initialized, so it needs its
+            // own provider
+            Some((daemon_state, _)) if app_init.stdin.is_empty() => provider::Provider::attach(
+                &mut daemon_state.borrow_mut().provider,
+                config.clone(),
+                config_dir.clone(),
+                app_init.env,
+                session,
+            ),
+            _ => sender.spawn_command(glib::clone!(
+                #[strong]
+                config,
+                #[strong]
+                config_dir,
+                #[strong(rename_to = stdin)]
+                app_init.stdin,
+                #[strong(rename_to = env)]
+                app_init.env,
+                move |_| {
+                    let (sessions_tx, sessions) = mpsc::unbounded_channel();
+                    let _ = sessions_tx.send(session);
+                    drop(sessions_tx);
+                    if let Err(why) = provider::worker(config, config_dir, sessions, stdin, env) {
+                        eprintln!("[anyrun] IPC worker returned an error: {why}");
+                    }
                 }
-            }
-        ));
+            )),
+        }
 
-        let widgets = view_output!();
         let model = Self {
             daemon_ctx,
-            config,
+            config: config.clone(),
             plugins: plugins_factory,
-            post_run_action: PostRunAction::None,
+            merged: merged_factory,
+            merged_sources: Vec::new(),
+            post_run_action: PostRunAction::Cancelled,
+            history: if config.frecency || config.max_query_history > 0 {
+                History::load()
+            } else {
+                History::default()
+            },
+            query_index: None,
+            query_draft: String::new(),
+            input_seq: 0,
+            query_seq: 0,
+            unanswered: Vec::new(),
+            waiting: Vec::new(),
+            queued_query: None,
+            slow_plugins: Vec::new(),
+            timed_out_plugins: Vec::new(),
             tx,
             css_provider,
+            config_dir,
+            initial_query: app_init.args.query.clone(),
+            select_all: app_init.args.select_all,
+            dmenu: app_init.args.dmenu,
+            password: app_init.args.password,
+            marked: Vec::new(),
+            shown: false,
+            action_menu: None,
+            unhandled: 0,
+            pending_marked: VecDeque::new(),
+            marked_output: Vec::new(),
+            selection: None,
+            output: app_init.args.output,
+            config_monitor,
+            config_error,
+            style_errors: Vec::new(),
         };
 
+        let widgets = view_output!();
+
         ComponentParts { model, widgets }
     }
 
@@ -326,41 +914,77 @@ impl Component for App {
                 width: mon_width,
                 height: mon_height,
             } => {
+                // Sent again whenever the window enters another monitor
+                if !self.shown {
+                    self.shown = true;
+                    self.emit_signal("Shown", None);
+                }
+
                 let width = self.config.width.to_val(mon_width);
-                let x = self.config.x.to_val(mon_width) - width / 2;
                 let height = self.config.height.to_val(mon_height);
-                let y = self.config.y.to_val(mon_height) - height / 2;
+                let (horizontal, vertical) = self.anchors(width, height, mon_width, mon_height);
 
                 // Layer shell parameters are set up here to make sure when the window
                 // appears it appears at the center of the screen, and is then repositioned.
                 // This is maybe still not optimal, but I don't think there is another way
                 // to do this in a reasonable way.
-                root.set_anchor(Edge::Left, true);
-                root.set_anchor(Edge::Top, true);
+                for edge in [Edge::Left, Edge::Right, Edge::Top, Edge::Bottom] {
+                    root.set_anchor(edge, false);
+                }
 
                 if self.config.close_on_click {
+                    root.set_anchor(Edge::Left, true);
+                    root.set_anchor(Edge::Top, true);
                     root.set_default_size(mon_width as i32, mon_height as i32);
+
+                    let (start, end) = match horizontal {
+                        Some((Edge::Right, margin)) => (mon_width as i32 - margin - width, margin),
+                        Some((_, margin)) => (margin, mon_width as i32 - margin - width),
+                        None => {
+                            let margin = (mon_width as i32 - width) / 2;
+                            (margin, margin)
+                        }
+                    };
                     widgets.main.set_halign(gtk::Align::Fill);
-                    widgets.main.set_margin_start(x);
-                    widgets.main.set_margin_top(y);
-                    widgets.main.set_margin_end(mon_width as i32 - x - width);
-                    widgets
-                        .main
-                        .set_margin_bottom(mon_height as i32 - y - height);
+                    widgets.main.set_margin_start(start);
+                    widgets.main.set_margin_end(end);
+
+                    // Aligning to the anchored edge makes the box grow away from it
+                    let (valign, top, bottom) = match vertical {
+                        Some((Edge::Bottom, margin)) => (gtk::Align::End, 0, margin),
+                        Some((_, margin)) => (gtk::Align::Start, margin, 0),
+                        None => (gtk::Align::Center, 0, 0),
+                    };
+                    widgets.main.set_valign(valign);
+                    widgets.main.set_margin_top(top);
+                    widgets.main.set_margin_bottom(bottom);
+                    widgets.main.set_size_request(-1, height);
                 } else {
                     root.set_default_size(width, height);
                     root.child().unwrap().set_size_request(width, height);
-                    root.set_margin(Edge::Left, x);
-                    root.set_margin(Edge::Top, y);
+                    for (edge, margin) in [horizontal, vertical].into_iter().flatten() {
+                        root.set_anchor(edge, true);
+                        root.set_margin(edge, margin);
+                    }
                 }
                 root.set_opacity(1.0); // Continuation of the Sway hack
                 widgets.entry.grab_focus_without_selecting();
 
-                // If show_results_immediately is enabled, trigger initial search with empty input
-                if self.config.show_results_immediately {
-                    let _ = self.tx.blocking_send(anyrun_provider_ipc::Request::Query {
-                        text: String::new(),
-                    });
+                if let Some(query) = self.initial_query.take() {
+                    // Query right away instead of waiting for the debounce
+                    widgets.entry.block_signal(&widgets.entry_changed);
+                    widgets.entry.set_text(&query);
+                    widgets.entry.unblock_signal(&widgets.entry_changed);
+
+                    if self.select_all {
+                        widgets.entry.select_region(0, -1);
+                    } else {
+                        widgets.entry.set_position(-1);
+                    }
+                    self.query(query, &sender);
+                } else if self.config.show_results_immediately {
+                    // If show_results_immediately is enabled, trigger initial search with empty input
+                    self.query(String::new(), &sender);
                 }
             }
             AppMsg::KeyPressed { key, modifier } => {
@@ -375,24 +999,29 @@ impl Component for App {
             }
             AppMsg::Action(action) => match action {
                 Action::Close => {
+                    let result = RunResult {
+                        action: self.post_run_action.clone(),
+                        selection: self.selection.clone(),
+                        query: widgets.entry.text().into(),
+                    };
+
                     if let Some((daemon_state, invocation)) = self.daemon_ctx.clone() {
-                        invocation.return_value(Some(
-                            &(
-                                serde_json::to_vec(&Ok::<_, ShowError>(&self.post_run_action))
-                                    .unwrap(),
-                            )
-                                .to_variant(),
-                        ));
+                        invocation.return_result(&result);
                         daemon_state.borrow_mut().sender = None;
-                    } else {
-                        match &self.post_run_action {
-                            PostRunAction::Stdout(bytes) => {
-                                io::stdout().lock().write_all(bytes).unwrap()
-                            }
-                            PostRunAction::None => (),
+                        // Responses still on their way would reach the next launcher, which can't
+                        // tell them apart from its own. Dropping the provider makes it quit once
+                        // this launcher is closed, and the next show starts a new one.
+                        if self.unhandled > 0 || self.unanswered.iter().any(|&n| n > 0) {
+                            daemon_state.borrow_mut().provider = None;
                         }
+                        self.emit_signal("Closed", None);
+                    } else {
+                        result.print(self.output);
                         root.application().unwrap().quit();
                     }
+                    if let Some(config_monitor) = &self.config_monitor {
+                        config_monitor.cancel();
+                    }
                     // Unload the style so a new one can be loaded on next show
                     gtk::style_context_remove_provider_for_display(
                         &WidgetExt::display(root),
@@ -403,6 +1032,12 @@ impl Component for App {
                     // exiting
                     let _ = self.tx.blocking_send(ipc::Request::Quit);
                     relm4::runtime_util::shutdown_all();
+
+                    // Like dmenu, exit with an error if nothing was selected. The client of the
+                    // daemon does the same based on the returned `RunResult`.
+                    if self.dmenu && self.daemon_ctx.is_none() && result.cancelled() {
+                        std::process::exit(1);
+                    }
                 }
                 Action::Select => {
                     if let Some((_, plugin, plugin_match)) = self.current_selection() {
@@ -410,47 +1045,223 @@ impl Component for App {
                             plugin: plugin.plugin_info.clone(),
                             selection: plugin_match.content.clone(),
                         });
+                        self.unhandled += 1;
+                        self.selection =
+                            Some(Selected::new(&plugin.plugin_info, &plugin_match.content));
+
+                        // Nothing typed into a password prompt is remembered
+                        if self.config.frecency && !self.password {
+                            let (plugin, title) = (
+                                plugin.plugin_info.name.to_string(),
+                                plugin_match.content.title.to_string(),
+                            );
+                            self.history
+                                .record_selection(&plugin, &title, &widgets.entry.text());
+                        }
+                        if self.config.max_query_history > 0 && !self.password {
+                            self.history
+                                .record_query(&widgets.entry.text(), self.config.max_query_history);
+                            // Recording reorders and trims the queries, so the index is outdated
+                            self.query_index = None;
+                        }
+                        if (self.config.frecency || self.config.max_query_history > 0)
+                            && !self.password
+                        {
+                            self.history.save();
+                        }
+                    } else if self.dmenu {
+                        // Like dmenu, submitting an input no line matches outputs the input itself
+                        self.post_run_action =
+                            PostRunAction::Stdout(widgets.entry.text().as_bytes().to_vec());
+                        sender.input(AppMsg::Action(Action::Close));
                     }
                 }
-                Action::Up => {
-                    if let Some((i, plugin, _)) = self.current_selection() {
-                        let matches = self.combined_matches();
-                        plugin
-                            .matches
-                            .widget()
-                            .select_row(Option::<&gtk::ListBoxRow>::None);
-                        if i > 0 {
-                            let (plugin, plugin_match) = matches[i - 1];
-                            plugin.matches.widget().select_row(Some(&plugin_match.row));
+                Action::SelectIndex(n) => {
+                    if let Some((plugin, plugin_match)) = n.checked_sub(1).and_then(|i| {
+                        self.combined_matches()
+                            .into_iter()
+                            .filter(|(_, plugin_match)| plugin_match.row.get_visible())
+                            .nth(i)
+                    }) {
+                        self.select(plugin, plugin_match);
+                        sender.input(AppMsg::Action(Action::Select));
+                    }
+                }
+                Action::Complete => {
+                    if let Some((_, _, plugin_match)) = self.current_selection() {
+                        let title = &plugin_match.content.title;
+                        let completion = if plugin_match.content.use_pango {
+                            pango::parse_markup(title, '\0')
+                                .map(|(_, text, _)| text.to_string())
+                                .unwrap_or_else(|_| title.to_string())
                         } else {
-                            let (plugin, plugin_match) = matches.last().unwrap();
-                            plugin.matches.widget().select_row(Some(&plugin_match.row));
+                            title.to_string()
+                        };
+                        widgets.entry.set_text(&completion);
+                        widgets.entry.set_position(-1);
+                    }
+                }
+                Action::HistoryPrev => {
+                    let index = match self.query_index {
+                        Some(i) => Some(i.saturating_sub(1)),
+                        None => {
+                            self.query_draft = widgets.entry.text().into();
+                            self.history.queries.len().checked_sub(1)
                         }
+                    };
+                    if let Some(query) = index.and_then(|index| self.history.queries.get(index)) {
+                        widgets.entry.set_text(query);
+                        self.query_index = index;
+                        widgets.entry.set_position(-1);
                     }
                 }
-                Action::Down => {
-                    if let Some((i, plugin, _)) = self.current_selection() {
-                        let matches = self.combined_matches();
-                        plugin
-                            .matches
-                            .widget()
-                            .select_row(Option::<&gtk::ListBoxRow>::None);
-                        if let Some((plugin, plugin_match)) = matches.get(i + 1) {
-                            plugin.matches.widget().select_row(Some(&plugin_match.row));
+                Action::ToggleMark => {
+                    if let Some((_, plugin, plugin_match)) = self.current_selection() {
+                        let marked = match self.mark_position(plugin, plugin_match) {
+                            Some(i) => {
+                                self.marked.remove(i);
+                                false
+                            }
+                            None => {
+                                self.marked.push((
+                                    plugin.plugin_info.clone(),
+                                    plugin_match.content.clone(),
+                                ));
+                                true
+                            }
+                        };
+                        plugin_match.row.set_class_active("marked", marked);
+                    }
+                }
+                Action::SelectMarked => {
+                    if self.marked.is_empty() {
+                        sender.input(AppMsg::Action(Action::Select));
+                    } else if self.pending_marked.is_empty() {
+                        // Copied so that changes to the marks can't affect the pending results
+                        self.pending_marked = self.marked.iter().cloned().collect();
+                        for (plugin, selection) in &self.pending_marked {
+                            let _ = self.tx.blocking_send(ipc::Request::Handle {
+                                plugin: plugin.clone(),
+                                selection: selection.clone(),
+                            });
+                            self.selection = Some(Selected::new(plugin, selection));
+                        }
+                        self.unhandled += self.pending_marked.len();
+                    }
+                }
+                Action::ActionMenu => {
+                    let row = self
+                        .current_selection()
+                        .map(|(_, _, plugin_match)| plugin_match.row.clone());
+                    if row.is_some() {
+                        self.action_menu = if self.action_menu == row { None } else { row };
+                    }
+                }
+                // There are no alternate actions to activate yet, so show that instead
+                Action::SelectAction(_) => {
+                    let row = self
+                        .current_selection()
+                        .map(|(_, _, plugin_match)| plugin_match.row.clone());
+                    if row.is_some() {
+                        self.action_menu = row;
+                    }
+                }
+                Action::HistoryNext => {
+                    if let Some(i) = self.query_index {
+                        if i + 1 < self.history.queries.len() {
+                            self.query_index = Some(i + 1);
+                            widgets.entry.set_text(&self.history.queries[i + 1]);
                         } else {
-                            let (plugin, plugin_match) = matches[0];
-                            plugin.matches.widget().select_row(Some(&plugin_match.row));
+                            self.query_index = None;
+                            widgets.entry.set_text(&self.query_draft);
                         }
+                        widgets.entry.set_position(-1);
                     }
                 }
+                Action::Up => {
+                    self.move_selection(|i, matches| if i > 0 { i - 1 } else { matches.len() - 1 })
+                }
+                Action::Down => self.move_selection(|i, matches| (i + 1) % matches.len()),
+                Action::PageUp => self.move_selection(|i, _| i.saturating_sub(PAGE_SIZE)),
+                Action::PageDown => {
+                    self.move_selection(|i, matches| (i + PAGE_SIZE).min(matches.len() - 1))
+                }
+                Action::First => self.move_selection(|_, _| 0),
+                Action::Last => self.move_selection(|_, matches| matches.len() - 1),
+                Action::NextPlugin => self.move_selection(|i, matches| {
+                    let (plugin, _) = matches[i];
+                    matches
+                        .iter()
+                        .skip(i)
+                        .position(|(other, _)| !std::ptr::eq(*other, plugin))
+                        .map_or(0, |offset| i + offset)
+                }),
+                Action::PrevPlugin => self.move_selection(|i, matches| {
+                    let start = group_start(matches, i);
+                    if start > 0 {
+                        group_start(matches, start - 1)
+                    } else {
+                        group_start(matches, matches.len() - 1)
+                    }
+                }),
             },
             AppMsg::EntryChanged(text) => {
-                let _ = self.tx.blocking_send(ipc::Request::Query { text });
+                // Editing a recalled query stops the navigation of the history
+                if self
+                    .query_index
+                    .is_some_and(|i| self.history.queries.get(i) != Some(&text))
+                {
+                    self.query_index = None;
+                }
+
+                if self.config.query_debounce == 0 {
+                    self.query(text, &sender);
+                } else {
+                    // Only the last input within the debounce interval is queried
+                    self.input_seq += 1;
+                    let seq = self.input_seq;
+                    glib::timeout_add_local_once(
+                        Duration::from_millis(self.config.query_debounce),
+                        glib::clone!(
+                            #[strong]
+                            sender,
+                            move || sender.input(AppMsg::Query { seq, text })
+                        ),
+                    );
+                }
+            }
+            AppMsg::Query { seq, text } => {
+                if seq == self.input_seq {
+                    self.query(text, &sender);
+                }
+            }
+            AppMsg::PluginSlow { plugin, seq } => {
+                if seq == self.query_seq && self.waiting[plugin] {
+                    self.plugins.send(plugin, PluginBoxInput::Slow);
+                    if !self.slow_plugins.contains(&plugin) {
+                        self.slow_plugins.push(plugin);
+                    }
+                }
+            }
+            AppMsg::PluginTimeout { plugin, seq } => {
+                if seq == self.query_seq && self.waiting[plugin] {
+                    self.waiting[plugin] = false;
+                    self.plugins.send(plugin, PluginBoxInput::TimedOut);
+                    self.slow_plugins.retain(|&i| i != plugin);
+                    if !self.timed_out_plugins.contains(&plugin) {
+                        self.timed_out_plugins.push(plugin);
+                    }
+                    self.send_queued_query(&sender);
+                }
             }
             AppMsg::PluginOutput(PluginBoxOutput::MatchesLoaded) => {
+                if self.config.layout == Layout::Merged {
+                    self.merge_matches();
+                }
+
                 let matches = self.combined_matches();
-                if let Some((plugin, plugin_match)) = self.combined_matches().first() {
-                    plugin.matches.widget().select_row(Some(&plugin_match.row));
+                if let Some((plugin, plugin_match)) = matches.first() {
+                    self.select(plugin, plugin_match);
                 }
 
                 if let Some(max_entries) = self.config.max_entries {
@@ -459,6 +1270,95 @@ impl Component for App {
                     }
                     self.plugins.broadcast(PluginBoxInput::MaybeHide);
                 }
+
+                // Number the first visible matches for `Action::SelectIndex`
+                let mut index = 0;
+                for (plugin, plugin_match) in &matches {
+                    plugin_match.row.set_class_active(
+                        "marked",
+                        self.mark_position(plugin, plugin_match).is_some(),
+                    );
+
+                    if plugin_match.row.get_visible() && index < 9 {
+                        index += 1;
+                        plugin_match.badge.set_label(&index.to_string());
+                        plugin_match
+                            .badge
+                            .set_visible(!self.config.hide_index_badges);
+                    } else {
+                        plugin_match.badge.set_visible(false);
+                    }
+                }
+            }
+            AppMsg::ConfigFileChanged(name) => match (name.as_str(), &self.config_dir) {
+                ("style.css", Some(config_dir)) => {
+                    self.style_errors.clear();
+                    load_style(&self.css_provider, config_dir);
+                }
+                // The config is only validated, changes to it take effect on the next show
+                ("config.ron", Some(config_dir)) => {
+                    self.config_error = match Config::load(config_dir) {
+                        Err(why @ ConfigError::Parse(_)) => {
+                            eprintln!("[anyrun] {why}");
+                            Some(why.to_string())
+                        }
+                        _ => None,
+                    };
+                }
+                _ => (),
+            },
+            AppMsg::StyleError(error) => self.style_errors.push(error),
+            AppMsg::SetQuery(text) => {
+                widgets.entry.set_text(&text);
+                widgets.entry.set_position(-1);
+            }
+            AppMsg::RemoteAction { action, invocation } => {
+                let visible = self
+                    .combined_matches()
+                    .into_iter()
+                    .filter(|(_, plugin_match)| plugin_match.row.get_visible())
+                    .count();
+
+                match action {
+                    Action::SelectIndex(n) if n == 0 || n > visible => invocation.return_error(
+                        gio::DBusError::InvalidArgs,
+                        &format!("There is no match {n}, the {visible} visible matches start at 1"),
+                    ),
+                    Action::SelectAction(n) => invocation.return_error(
+                        gio::DBusError::InvalidArgs,
+                        &format!(
+                            "The selected match has no action {n}, plugins can't provide any yet"
+                        ),
+                    ),
+                    action => {
+                        invocation.return_value(None);
+                        sender.input(AppMsg::Action(action));
+                    }
+                }
+            }
+            AppMsg::GetState(invocation) => {
+                let matches = self
+                    .combined_matches()
+                    .into_iter()
+                    .filter(|(_, plugin_match)| plugin_match.row.get_visible())
+                    .map(|(plugin, plugin_match)| {
+                        (
+                            plugin.plugin_info.name.to_string(),
+                            plugin_match.content.title.to_string(),
+                            plugin_match
+                                .content
+                                .description
+                                .as_ref()
+                                .map(|desc| desc.to_string())
+                                .unwrap_or_default(),
+                            plugin_match.row.is_selected(),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+
+                invocation.return_value(Some(
+                    &(widgets.entry.text().to_string(), matches).to_variant(),
+                ));
             }
             // Handle clicked selections
             AppMsg::PluginOutput(PluginBoxOutput::RowSelected(index)) => {
@@ -487,7 +1387,13 @@ impl Component for App {
                 let mut guard = self.plugins.guard();
                 for info in info {
                     guard.push_back((info, self.config.clone()));
+                    self.unanswered.push(0);
+                    self.waiting.push(false);
                 }
+                drop(guard);
+
+                // Inputs before the plugins are known are queued
+                self.send_queued_query(&sender);
             }
             ipc::Response::Matches { plugin, matches } => {
                 let i = self
@@ -503,15 +1409,65 @@ impl Component for App {
                     })
                     .unwrap();
 
-                self.plugins.send(i, PluginBoxInput::Matches(matches));
+                // With a single query in flight, a plugin that has one unanswered query is answering
+                // the last one. After a timeout it is unknown which query the answers belong to, so
+                // they are discarded until the plugin has answered all of them.
+                self.unanswered[i] = self.unanswered[i].saturating_sub(1);
+                if self.unanswered[i] == 0 {
+                    self.waiting[i] = false;
+                    self.slow_plugins.retain(|&plugin| plugin != i);
+                    self.timed_out_plugins.retain(|&plugin| plugin != i);
+
+                    let matches = if self.config.frecency {
+                        self.rank_by_frecency(&plugin.name, matches, &widgets.entry.text())
+                    } else {
+                        matches
+                    };
+
+                    self.plugins.send(i, PluginBoxInput::Matches(matches));
+                    self.send_queued_query(&sender);
+                }
+            }
+            // The results of the marked matches are combined, and only printed ones are kept
+            ipc::Response::Handled { plugin, result } if !self.pending_marked.is_empty() => {
+                self.unhandled = self.unhandled.saturating_sub(1);
+                // The marked matches are handled in order
+                if let Some((_, selection)) = self.pending_marked.pop_front() {
+                    self.emit_signal(
+                        "Activated",
+                        Some(&(plugin.name.to_string(), selection.title.to_string()).to_variant()),
+                    );
+                }
+
+                if let HandleResult::Stdout(rvec) = result {
+                    self.marked_output.push(rvec.into());
+                }
+
+                if self.pending_marked.is_empty() {
+                    self.post_run_action = if self.marked_output.is_empty() {
+                        PostRunAction::None
+                    } else {
+                        PostRunAction::Stdout(self.marked_output.join(&b'\n'))
+                    };
+                    sender.input(AppMsg::Action(Action::Close));
+                }
             }
             ipc::Response::Handled { plugin, result } => {
+                self.unhandled = self.unhandled.saturating_sub(1);
+                if let Some(selection) = &self.selection {
+                    self.emit_signal(
+                        "Activated",
+                        Some(&(plugin.name.to_string(), selection.title.clone()).to_variant()),
+                    );
+                }
+
                 match result {
-                    HandleResult::Close => sender.input(AppMsg::Action(Action::Close)),
+                    HandleResult::Close => {
+                        self.post_run_action = PostRunAction::None;
+                        sender.input(AppMsg::Action(Action::Close));
+                    }
                     HandleResult::Refresh(exclusive) => {
-                        let _ = self.tx.blocking_send(ipc::Request::Query {
-                            text: widgets.entry.text().into(),
-                        });
+                        self.query(widgets.entry.text().into(), &sender);
                         if exclusive {
                             for (i, plugin_box) in self.plugins.iter().enumerate() {
                                 // While normally true, in this case the function addresses will be consistent
@@ -520,6 +1476,13 @@ impl Component for App {
                                     self.plugins.send(i, PluginBoxInput::Enable(false));
                                 }
                             }
+
+                            // The plugin has entered a state of its own, which the next show
+                            // should not start in. Dropping the provider kept by the daemon makes
+                            // it quit once this launcher closes, and the next show starts a new one.
+                            if let Some((daemon_state, _)) = &self.daemon_ctx {
+                                daemon_state.borrow_mut().provider = None;
+                            }
                         } else {
                             self.plugins.broadcast(PluginBoxInput::Enable(true));
                         }
@@ -542,10 +1505,10 @@ impl Component for App {
                                 eprintln!("[anyrun] Error setting clipboard content: {why}");
                             }
                         }
+                        self.post_run_action = PostRunAction::None;
                         sender.input(AppMsg::Action(Action::Close));
                     }
                     HandleResult::Stdout(rvec) => {
-                        io::stdout().lock().write_all(&rvec).unwrap();
                         self.post_run_action = PostRunAction::Stdout(rvec.into());
                         sender.input(AppMsg::Action(Action::Close));
                     }
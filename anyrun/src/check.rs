@@ -0,0 +1,126 @@
+use std::{cell::RefCell, env, io, path::PathBuf, rc::Rc};
+
+use anyrun_provider_ipc as ipc;
+use gtk::glib;
+use gtk4 as gtk;
+
+use crate::{
+    app,
+    config::{self, Config, ConfigError},
+    Args,
+};
+
+/// The directories relative plugin paths are looked up from, in the same order as the provider
+fn plugin_dirs(config_dir: &str) -> Vec<PathBuf> {
+    env::var("ANYRUN_PLUGINS")
+        .map(|dirs| dirs.split(':').map(PathBuf::from).collect())
+        .unwrap_or_else(|_| Vec::new())
+        .into_iter()
+        .chain([PathBuf::from(config_dir).join("plugins")])
+        .chain(
+            ipc::CONFIG_DIRS
+                .iter()
+                .map(|dir| PathBuf::from(dir).join("plugins")),
+        )
+        .collect()
+}
+
+/// Validate the config directory, printing every problem found. Returns whether the config is valid.
+pub fn check_config(args: &Args) -> bool {
+    let Some(config_dir) = config::find_config_dir(args.config_dir.clone()) else {
+        eprintln!("[anyrun] Error: No config directory found in any searched paths");
+        return false;
+    };
+    println!("[anyrun] Checking {config_dir}");
+
+    let mut errors = Vec::new();
+
+    let mut config = match Config::load(&config_dir) {
+        Ok(config) => config,
+        Err(ConfigError::Io(why)) if why.kind() == io::ErrorKind::NotFound => {
+            eprintln!(
+                "[anyrun] Warning: {config_dir}/config.ron does not exist, default values are used"
+            );
+            Config::default()
+        }
+        Err(why) => {
+            errors.push(why.to_string());
+            Config::default()
+        }
+    };
+    if !args.apply_overrides(&mut config) {
+        errors.push(format!(
+            "Profile `{}` does not exist",
+            args.profile.as_deref().unwrap_or_default()
+        ));
+    }
+
+    let plugin_dirs = plugin_dirs(&config_dir);
+    for plugin in &config.plugins {
+        let found = if plugin.is_absolute() {
+            plugin.exists()
+        } else {
+            plugin_dirs.iter().any(|dir| dir.join(plugin).exists())
+        };
+
+        if !found {
+            errors.push(format!(
+                "Plugin `{}` not found, searched in: {}",
+                plugin.display(),
+                if plugin.is_absolute() {
+                    plugin.display().to_string()
+                } else {
+                    plugin_dirs
+                        .iter()
+                        .map(|dir| dir.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }
+            ));
+        }
+    }
+
+    for (i, keybind) in config.keybinds.iter().enumerate() {
+        if config.keybinds[..i].iter().any(|other| {
+            other.key == keybind.key
+                && other.ctrl == keybind.ctrl
+                && other.alt == keybind.alt
+                && other.shift == keybind.shift
+        }) {
+            eprintln!(
+                "[anyrun] Warning: Keybind `{}` is bound multiple times, only the first one is used",
+                keybind.key.name().map(|name| name.to_string()).unwrap_or_default()
+            );
+        }
+    }
+
+    let css_path = PathBuf::from(&config_dir).join("style.css");
+    if !css_path.exists() {
+        eprintln!(
+            "[anyrun] Warning: {config_dir}/style.css does not exist, the default style is used"
+        );
+    } else if let Err(why) = gtk::init() {
+        eprintln!("[anyrun] Warning: Failed to initialize GTK, skipping the stylesheet: {why}");
+    } else {
+        let style_errors = Rc::new(RefCell::new(Vec::new()));
+        let css_provider = gtk::CssProvider::new();
+        css_provider.connect_parsing_error(glib::clone!(
+            #[strong]
+            style_errors,
+            move |_, section, why| style_errors
+                .borrow_mut()
+                .push(app::style_error(section, why))
+        ));
+        css_provider.load_from_path(css_path);
+        errors.extend(style_errors.take());
+    }
+
+    for error in &errors {
+        eprintln!("[anyrun] Error: {error}");
+    }
+
+    if errors.is_empty() {
+        println!("[anyrun] No errors found");
+    }
+    errors.is_empty()
+}
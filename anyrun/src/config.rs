@@ -1,51 +1,183 @@
 use anyrun_macros::ConfigArgs;
+use anyrun_provider_ipc as ipc;
 use clap::ValueEnum;
 use gtk::gdk;
 use gtk4 as gtk;
 use serde::{de::Visitor, Deserialize, Deserializer, Serialize};
-use std::path::PathBuf;
+use std::{collections::HashMap, env, fmt, fs, io, path::PathBuf, str::FromStr};
+
+/// The config directory to use, the user's config directory taking precedence over the system wide ones
+pub fn find_config_dir(config_dir: Option<String>) -> Option<String> {
+    let user_dir = env::var("XDG_CONFIG_HOME")
+        .map(|c| format!("{c}/anyrun"))
+        .or_else(|_| env::var("HOME").map(|h| format!("{h}/.config/anyrun")))
+        .unwrap();
+
+    config_dir.map(Some).unwrap_or_else(|| {
+        if PathBuf::from(&user_dir).exists() {
+            Some(user_dir)
+        } else {
+            ipc::CONFIG_DIRS
+                .iter()
+                .map(|path| path.to_string())
+                .find(|path| PathBuf::from(path).exists())
+        }
+    })
+}
 
 #[derive(Deserialize, ConfigArgs)]
 #[config_args(pub)]
 pub struct Config {
+    /// The horizontal position, `absolute:<pixels>` or `fraction:<fraction of the screen>`
     #[serde(default = "Config::default_x")]
     pub x: RelativeNum,
+    /// The vertical position, works the same as `x`
     #[serde(default = "Config::default_y")]
     pub y: RelativeNum,
+    /// The width of the runner
     #[serde(default = "Config::default_width")]
     pub width: RelativeNum,
+    /// The minimum height of the runner, it expands to fit all the entries
     #[serde(default = "Config::default_height")]
     pub height: RelativeNum,
+    /// Which part of the screen the runner is placed relative to
+    #[serde(default = "Config::default_position")]
+    pub position: Position,
 
+    // Overridden with `--plugin` and `--add-plugin` instead
+    #[config_args(no_arg)]
     #[serde(default = "Config::default_plugins")]
     pub plugins: Vec<PathBuf>,
 
+    /// The path to the `anyrun-provider` executable
     #[serde(default = "Config::default_provider")]
     pub provider: PathBuf,
 
+    /// Hide match and plugin info icons
     #[serde(default)]
     pub hide_icons: bool,
+    /// Hide the plugin info panel
     #[serde(default)]
     pub hide_plugin_info: bool,
+    /// Hide the numbers shown next to the first nine matches
+    #[serde(default)]
+    pub hide_index_badges: bool,
+    /// Ignore exclusive zones, for example of bars
     #[serde(default)]
     pub ignore_exclusive_zones: bool,
+    /// Close the runner when a click outside the main box is received
     #[serde(default)]
     pub close_on_click: bool,
+    /// Show search results immediately when the runner is shown
     #[serde(default)]
     pub show_results_immediately: bool,
+    /// Limit the amount of entries shown in total, `none` for no limit
     #[serde(default)]
     pub max_entries: Option<u32>,
+    /// Time in milliseconds to wait after the input stops changing before querying the plugins
+    #[serde(default)]
+    pub query_debounce: u64,
+    /// Time in milliseconds a plugin has to answer a query, 0 disables the timeout. A new input is
+    /// only queried once every plugin has answered or timed out.
+    #[serde(default = "Config::default_plugin_timeout")]
+    pub plugin_timeout: u64,
+    #[config_args(no_arg)]
+    #[serde(default)]
+    pub plugin_timeouts: HashMap<String, u64>,
+    /// The layer shell layer of the runner
     #[serde(default = "Config::default_layer")]
     pub layer: Layer,
+    /// How keyboard focus is taken
     #[serde(default = "Config::default_keyboard_mode")]
     pub keyboard_mode: KeyboardMode,
+    /// Whether matches are grouped by plugin or merged into a single list
+    #[serde(default = "Config::default_layout")]
+    pub layout: Layout,
+    /// The monitor to show the runner on, `focused`, an index or a connector name
+    #[serde(default = "Config::default_monitor")]
+    pub monitor: Monitor,
+    /// Multipliers for the scores of the matches of plugins with `Layout::Merged`. A match scores
+    /// the weight of its plugin divided by its rank within the plugin, so the weights decide how
+    /// plugins compare.
+    #[config_args(no_arg)]
+    #[serde(default)]
+    pub plugin_weights: HashMap<String, f64>,
 
-    #[config_args(skip)]
+    /// Rank frequently and recently selected matches higher
+    #[serde(default)]
+    pub frecency: bool,
+    /// The time in hours after which the weight of a past selection is halved
+    #[serde(default = "Config::default_frecency_half_life")]
+    pub frecency_half_life: f64,
+    /// How many submitted inputs are remembered in the history file, 0 disables the query history
+    #[serde(default = "Config::default_max_query_history")]
+    pub max_query_history: usize,
+
+    #[config_args(no_arg)]
     #[serde(default = "Config::default_keybinds")]
     pub keybinds: Vec<Keybind>,
+
+    /// Named sets of overrides for the other options, selected with `--profile`
+    #[config_args(skip)]
+    #[serde(default)]
+    pub profiles: HashMap<String, ConfigArgs>,
+}
+
+/// An error encountered while loading `config.ron`
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(why) => write!(f, "Failed to read config file: {why}"),
+            Self::Parse(why) => write!(
+                f,
+                "Failed to parse config file at config.ron:{}:{}: {}",
+                why.position.line, why.position.col, why.code
+            ),
+        }
+    }
 }
 
 impl Config {
+    /// Load `config.ron` from the config directory
+    pub fn load(config_dir: &str) -> Result<Self, ConfigError> {
+        let content = fs::read(format!("{config_dir}/config.ron")).map_err(ConfigError::Io)?;
+        // Allows leaving out `Some` for the options set by profiles
+        ron::Options::default()
+            .with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME)
+            .from_bytes(&content)
+            .map_err(ConfigError::Parse)
+    }
+
+    /// Apply the overrides of a profile, returns `false` if it doesn't exist
+    pub fn apply_profile(&mut self, name: &str) -> bool {
+        match self.profiles.get(name).cloned() {
+            Some(profile) => {
+                self.merge_opt(profile);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The multiplier applied to the scores of the matches of a plugin
+    pub fn plugin_weight(&self, name: &str) -> f64 {
+        self.plugin_weights.get(name).copied().unwrap_or(1.0)
+    }
+
+    /// The time in milliseconds a plugin has to answer a query, 0 meaning no timeout
+    pub fn plugin_timeout(&self, name: &str) -> u64 {
+        self.plugin_timeouts
+            .get(name)
+            .copied()
+            .unwrap_or(self.plugin_timeout)
+    }
+
     fn default_x() -> RelativeNum {
         RelativeNum::Fraction(0.5)
     }
@@ -62,6 +194,10 @@ impl Config {
         RelativeNum::Absolute(1)
     }
 
+    fn default_position() -> Position {
+        Position::Top
+    }
+
     fn default_plugins() -> Vec<PathBuf> {
         vec![
             "libapplications.so".into(),
@@ -83,7 +219,48 @@ impl Config {
         KeyboardMode::Exclusive
     }
 
+    fn default_layout() -> Layout {
+        Layout::Grouped
+    }
+
+    fn default_monitor() -> Monitor {
+        Monitor::Focused
+    }
+
+    fn default_plugin_timeout() -> u64 {
+        5000
+    }
+
+    fn default_frecency_half_life() -> f64 {
+        72.0
+    }
+
+    fn default_max_query_history() -> usize {
+        0
+    }
+
     fn default_keybinds() -> Vec<Keybind> {
+        let quick_select = [
+            gdk::Key::_1,
+            gdk::Key::_2,
+            gdk::Key::_3,
+            gdk::Key::_4,
+            gdk::Key::_5,
+            gdk::Key::_6,
+            gdk::Key::_7,
+            gdk::Key::_8,
+            gdk::Key::_9,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, key)| Keybind {
+            ctrl: false,
+            alt: true,
+            shift: false,
+            key,
+            action: Action::SelectIndex(i + 1),
+        });
+
         vec![
             Keybind {
                 ctrl: false,
@@ -127,7 +304,101 @@ impl Config {
                 key: gdk::Key::Tab,
                 action: Action::Down,
             },
+            Keybind {
+                ctrl: false,
+                alt: false,
+                shift: false,
+                key: gdk::Key::Page_Up,
+                action: Action::PageUp,
+            },
+            Keybind {
+                ctrl: false,
+                alt: false,
+                shift: false,
+                key: gdk::Key::Page_Down,
+                action: Action::PageDown,
+            },
+            Keybind {
+                ctrl: true,
+                alt: false,
+                shift: false,
+                key: gdk::Key::Home,
+                action: Action::First,
+            },
+            Keybind {
+                ctrl: true,
+                alt: false,
+                shift: false,
+                key: gdk::Key::End,
+                action: Action::Last,
+            },
+            Keybind {
+                ctrl: true,
+                alt: false,
+                shift: false,
+                key: gdk::Key::Down,
+                action: Action::NextPlugin,
+            },
+            Keybind {
+                ctrl: true,
+                alt: false,
+                shift: false,
+                key: gdk::Key::Up,
+                action: Action::PrevPlugin,
+            },
+            Keybind {
+                ctrl: true,
+                alt: false,
+                shift: false,
+                key: gdk::Key::Tab,
+                action: Action::Complete,
+            },
+            Keybind {
+                ctrl: true,
+                alt: false,
+                shift: false,
+                key: gdk::Key::p,
+                action: Action::HistoryPrev,
+            },
+            Keybind {
+                ctrl: true,
+                alt: false,
+                shift: false,
+                key: gdk::Key::n,
+                action: Action::HistoryNext,
+            },
+            Keybind {
+                ctrl: true,
+                alt: false,
+                shift: false,
+                key: gdk::Key::space,
+                action: Action::ToggleMark,
+            },
+            Keybind {
+                ctrl: false,
+                alt: false,
+                shift: true,
+                key: gdk::Key::Return,
+                action: Action::SelectMarked,
+            },
+            Keybind {
+                ctrl: true,
+                alt: false,
+                shift: false,
+                key: gdk::Key::Return,
+                action: Action::SelectAction(1),
+            },
+            Keybind {
+                ctrl: false,
+                alt: false,
+                shift: false,
+                key: gdk::Key::Menu,
+                action: Action::ActionMenu,
+            },
         ]
+        .into_iter()
+        .chain(quick_select)
+        .collect()
     }
 }
 impl Default for Config {
@@ -137,17 +408,29 @@ impl Default for Config {
             y: Self::default_y(),
             width: Self::default_width(),
             height: Self::default_height(),
+            position: Self::default_position(),
             plugins: Self::default_plugins(),
             provider: Self::default_provider(),
             hide_icons: false,
             hide_plugin_info: false,
+            hide_index_badges: false,
             ignore_exclusive_zones: false,
             close_on_click: false,
             show_results_immediately: false,
             max_entries: None,
+            query_debounce: 0,
+            plugin_timeout: Self::default_plugin_timeout(),
+            plugin_timeouts: HashMap::new(),
             layer: Self::default_layer(),
             keyboard_mode: Self::default_keyboard_mode(),
+            layout: Self::default_layout(),
+            monitor: Self::default_monitor(),
+            plugin_weights: HashMap::new(),
+            frecency: false,
+            frecency_half_life: Self::default_frecency_half_life(),
+            max_query_history: Self::default_max_query_history(),
             keybinds: Self::default_keybinds(),
+            profiles: HashMap::new(),
         }
     }
 }
@@ -166,6 +449,37 @@ pub enum KeyboardMode {
     OnDemand,
 }
 
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// Matches are grouped by plugin, in the order of `plugins`
+    Grouped,
+    /// Matches of all plugins are interleaved by the plugin weight divided by their rank within
+    /// their plugin
+    Merged,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub enum Monitor {
+    /// Let the compositor choose, which is usually the focused monitor
+    Focused,
+    /// The monitor at the index in the list of monitors, starting from 0
+    Index(u32),
+    /// The monitor with the connector name, for example `DP-1`
+    Connector(String),
+}
+
+impl From<&str> for Monitor {
+    fn from(value: &str) -> Self {
+        if value == "focused" {
+            Self::Focused
+        } else if let Ok(index) = value.parse() {
+            Self::Index(index)
+        } else {
+            Self::Connector(value.to_string())
+        }
+    }
+}
+
 // Could have a better name
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub enum RelativeNum {
@@ -182,14 +496,26 @@ impl RelativeNum {
     }
 }
 
-impl From<&str> for RelativeNum {
-    fn from(value: &str) -> Self {
-        let (ty, val) = value.split_once(':').expect("Invalid RelativeNum value");
+impl FromStr for RelativeNum {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (ty, val) = value
+            .split_once(':')
+            .ok_or("expected `absolute:<pixels>` or `fraction:<fraction>`")?;
 
         match ty {
-            "absolute" => Self::Absolute(val.parse().unwrap()),
-            "fraction" => Self::Fraction(val.parse().unwrap()),
-            _ => panic!("Invalid type of value"),
+            "absolute" => val
+                .parse()
+                .map(Self::Absolute)
+                .map_err(|why| why.to_string()),
+            "fraction" => val
+                .parse()
+                .map(Self::Fraction)
+                .map_err(|why| why.to_string()),
+            _ => Err(format!(
+                "invalid type `{ty}`, expected `absolute` or `fraction`"
+            )),
         }
     }
 }
@@ -200,9 +526,40 @@ pub enum Action {
     Select,
     Up,
     Down,
+    /// Move the selection up by a page of matches
+    PageUp,
+    /// Move the selection down by a page of matches
+    PageDown,
+    /// Select the first match
+    First,
+    /// Select the last match
+    Last,
+    /// Select the first match of the next plugin
+    NextPlugin,
+    /// Select the first match of the previous plugin
+    PrevPlugin,
+    /// Activate the n-th visible match, starting from 1
+    SelectIndex(usize),
+    /// Replace the input with the title of the selected match
+    Complete,
+    /// Replace the input with the previous entry in the query history
+    HistoryPrev,
+    /// Replace the input with the next entry in the query history
+    HistoryNext,
+    /// Mark or unmark the selected match for `SelectMarked`
+    ToggleMark,
+    /// Activate all marked matches, combining their output with newlines. Works like `Select` if
+    /// nothing is marked.
+    SelectMarked,
+    /// Show or hide the alternate actions of the selected match. Plugins can't provide any yet, so
+    /// this only tells that there are none.
+    ActionMenu,
+    /// Activate the selected match with its n-th alternate action, starting from 1. Plugins can't
+    /// provide any yet, so this shows the action menu instead.
+    SelectAction(usize),
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Deserialize, Clone, Debug)]
 pub struct Keybind {
     #[serde(default)]
     pub ctrl: bool,
@@ -233,7 +590,7 @@ impl Keybind {
             where
                 E: serde::de::Error,
             {
-                gdk::Key::from_name(v).ok_or(E::custom("Key name is not valid"))
+                gdk::Key::from_name(v).ok_or(E::custom(format!("Key name `{v}` is not valid")))
             }
         }
 
@@ -241,8 +598,17 @@ impl Keybind {
     }
 }
 
-#[derive(Deserialize, Clone, ValueEnum)]
-enum Position {
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, ValueEnum)]
+pub enum Position {
+    /// Positioned by `x` and `y`, growing downwards
     Top,
+    /// Positioned by `x`, with `y` as the distance from the bottom, growing upwards
+    Bottom,
+    /// Centered on the monitor, growing in both directions
     Center,
+    /// `x` and `y` are the distances from the corner
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
 }
@@ -0,0 +1,135 @@
+use std::{
+    env, fs, io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The maximum amount of selections kept in the history file
+const MAX_SELECTIONS: usize = 1000;
+
+/// A match that was selected by the user
+#[derive(Deserialize, Serialize)]
+pub struct Selection {
+    pub plugin: String,
+    pub title: String,
+    /// The text that was in the input when the match was selected
+    pub input: String,
+    /// Seconds since the UNIX epoch
+    pub timestamp: u64,
+}
+
+/// History persisted between runs, stored in `$XDG_STATE_HOME/anyrun/history.json`
+#[derive(Deserialize, Serialize, Default)]
+pub struct History {
+    #[serde(default)]
+    pub selections: Vec<Selection>,
+    /// Previously submitted inputs, oldest first
+    #[serde(default)]
+    pub queries: Vec<String>,
+}
+
+impl History {
+    fn path() -> Option<PathBuf> {
+        env::var("XDG_STATE_HOME")
+            .map(|state| format!("{state}/anyrun"))
+            .or_else(|_| env::var("HOME").map(|home| format!("{home}/.local/state/anyrun")))
+            .ok()
+            .map(|dir| PathBuf::from(dir).join("history.json"))
+    }
+
+    pub fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+
+        match fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|why| {
+                eprintln!("[anyrun] Failed to parse history file, ignoring it: {why}");
+                Self::default()
+            }),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(why) => {
+                eprintln!("[anyrun] Failed to read history file: {why}");
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = Self::path() else {
+            return;
+        };
+
+        if let Err(why) = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&path, serde_json::to_vec(self).unwrap()))
+        {
+            eprintln!("[anyrun] Failed to write history file: {why}");
+        }
+    }
+
+    /// Remove the history file
+    pub fn clear() -> io::Result<()> {
+        match Self::path().map(fs::remove_file) {
+            Some(Err(why)) if why.kind() != io::ErrorKind::NotFound => Err(why),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn record_selection(&mut self, plugin: &str, title: &str, input: &str) {
+        self.selections.push(Selection {
+            plugin: plugin.to_string(),
+            title: title.to_string(),
+            input: input.to_string(),
+            timestamp: now(),
+        });
+
+        if self.selections.len() > MAX_SELECTIONS {
+            self.selections
+                .drain(..self.selections.len() - MAX_SELECTIONS);
+        }
+    }
+
+    /// Add a submitted input to the end of the query history, removing an earlier occurrence of it
+    pub fn record_query(&mut self, query: &str, max: usize) {
+        if query.is_empty() {
+            return;
+        }
+
+        self.queries.retain(|existing| existing != query);
+        self.queries.push(query.to_string());
+
+        if self.queries.len() > max {
+            self.queries.drain(..self.queries.len() - max);
+        }
+    }
+
+    /// The frecency of a match, every earlier selection of it with a compatible input contributes
+    /// a weight that halves every `half_life` hours.
+    pub fn frecency(&self, plugin: &str, title: &str, input: &str, half_life: f64) -> f64 {
+        let now = now();
+
+        self.selections
+            .iter()
+            .filter(|selection| {
+                selection.plugin == plugin
+                    && selection.title == title
+                    && (input.starts_with(&selection.input) || selection.input.starts_with(input))
+            })
+            .map(|selection| {
+                let age = now.saturating_sub(selection.timestamp) as f64 / 3600.0;
+                0.5_f64.powf(age / half_life)
+            })
+            .sum()
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
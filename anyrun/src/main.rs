@@ -1,6 +1,8 @@
 use std::{
     cell::RefCell,
-    io::{self, IsTerminal, Read, Write},
+    collections::HashMap,
+    io::{self, IsTerminal, Read},
+    path::PathBuf,
     rc::Rc,
 };
 
@@ -16,15 +18,46 @@ use serde::{Deserialize, Serialize};
 use crate::config::{Config, ConfigArgs};
 
 mod app;
+mod check;
 mod config;
+mod history;
 mod plugin_box;
 mod provider;
 
 /// The interface through which the daemon
-/// responds to launch requests
+/// responds to launch requests.
+///
+/// `Open` and `Hide` are typed for use from other programs, `Run`, `Toggle` and `Close` exchange
+/// JSON in byte arrays and are used by the CLI to forward all of its arguments. `Show` is what
+/// `Run` used to be, and is kept for clients from before it.
 const INTERFACE_XML: &str = r#"
 <node>
     <interface name="org.anyrun.Anyrun">
+        <method name="Open">
+            <arg type="a{sv}" name="options" direction="in"/>
+            <arg type="b" name="cancelled" direction="out"/>
+            <arg type="s" name="query" direction="out"/>
+            <arg type="ay" name="output" direction="out"/>
+            <arg type="s" name="plugin" direction="out"/>
+            <arg type="s" name="title" direction="out"/>
+            <arg type="s" name="description" direction="out"/>
+            <arg type="t" name="id" direction="out"/>
+        </method>
+        <method name="Hide"></method>
+        <method name="SetQuery">
+            <arg type="s" name="text" direction="in"/>
+        </method>
+        <method name="Action">
+            <arg type="s" name="name" direction="in"/>
+        </method>
+        <method name="GetState">
+            <arg type="s" name="query" direction="out"/>
+            <arg type="a(sssb)" name="matches" direction="out"/>
+        </method>
+        <method name="Run">
+            <arg type="ay" name="args" direction="in"/>
+            <arg type="ay" name="result" direction="out"/>
+        </method>
         <method name="Show">
             <arg type="ay" name="args" direction="in"/>
             <arg type="ay" name="result" direction="out"/>
@@ -32,7 +65,17 @@ const INTERFACE_XML: &str = r#"
         <method name="Close">
             <arg type="ay" name="result" direction="out"/>
         </method>
+        <method name="Toggle">
+            <arg type="ay" name="args" direction="in"/>
+            <arg type="ay" name="result" direction="out"/>
+        </method>
         <method name="Quit"></method>
+        <signal name="Shown"></signal>
+        <signal name="Closed"></signal>
+        <signal name="Activated">
+            <arg type="s" name="plugin"/>
+            <arg type="s" name="title"/>
+        </signal>
     </interface>
 </node> 
 "#;
@@ -47,9 +90,20 @@ pub enum CloseError {
     NotShowed,
 }
 
+/// Error names returned by the typed methods
+const ERROR_ALREADY_VISIBLE: &str = "org.anyrun.Anyrun.Error.AlreadyVisible";
+const ERROR_NOT_VISIBLE: &str = "org.anyrun.Anyrun.Error.NotVisible";
+
 enum InterfaceMethod {
+    Open(glib::VariantDict),
+    Hide,
+    SetQuery(String),
+    Action(config::Action),
+    GetState,
+    Run(Show),
     Show(Show),
     Close,
+    Toggle(Show),
     Quit,
 }
 
@@ -61,8 +115,30 @@ impl DBusMethodCall for InterfaceMethod {
         params: glib::Variant,
     ) -> Result<Self, glib::Error> {
         match method {
+            "Open" => Ok(params
+                .get::<(glib::VariantDict,)>()
+                .map(|(options,)| Self::Open(options))),
+            "Hide" => Ok(Some(Self::Hide)),
+            "SetQuery" => Ok(params
+                .get::<(String,)>()
+                .map(|(text,)| Self::SetQuery(text))),
+            // Actions are named like in the config, for example `Close` or `SelectIndex(1)`
+            "Action" => match params.get::<(String,)>() {
+                Some((name,)) => ron::from_str(&name)
+                    .map(|action| Some(Self::Action(action)))
+                    .map_err(|why| {
+                        glib::Error::new(
+                            gio::DBusError::InvalidArgs,
+                            &format!("Invalid action `{name}`: {why}"),
+                        )
+                    }),
+                None => Ok(None),
+            },
+            "GetState" => Ok(Some(Self::GetState)),
+            "Run" => Ok(params.get::<Show>().map(Self::Run)),
             "Show" => Ok(params.get::<Show>().map(Self::Show)),
             "Close" => Ok(Some(Self::Close)),
+            "Toggle" => Ok(params.get::<Show>().map(Self::Toggle)),
             "Quit" => Ok(Some(Self::Quit)),
             _ => Err(glib::Error::new(
                 gio::DBusError::UnknownMethod,
@@ -75,13 +151,105 @@ impl DBusMethodCall for InterfaceMethod {
     }
 }
 
+/// Build the launch parameters from the options of the typed `Open` method
+fn app_init_from_options(options: &glib::VariantDict) -> Result<app::AppInit, glib::Error> {
+    let invalid = |why: glib::VariantTypeMismatchError| {
+        glib::Error::new(gio::DBusError::InvalidArgs, &why.to_string())
+    };
+
+    let mut args = Args::parse_from(["anyrun"]);
+    args.query = options.lookup("query").map_err(invalid)?;
+    args.select_all = options
+        .lookup("select-all")
+        .map_err(invalid)?
+        .unwrap_or_default();
+    args.profile = options.lookup("profile").map_err(invalid)?;
+    args.plugin = options
+        .lookup::<Vec<String>>("plugins")
+        .map_err(invalid)?
+        .unwrap_or_default()
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    args.config.monitor = options
+        .lookup::<String>("monitor")
+        .map_err(invalid)?
+        .map(|monitor| config::Monitor::from(monitor.as_str()));
+
+    Ok(app::AppInit {
+        args,
+        stdin: options
+            .lookup("stdin")
+            .map_err(invalid)?
+            .unwrap_or_default(),
+        env: options
+            .lookup::<HashMap<String, String>>("env")
+            .map_err(invalid)?
+            .unwrap_or_default()
+            .into_iter()
+            .collect(),
+    })
+}
+
+/// Decode the launch parameters the CLI sends to the JSON methods
+fn app_init_from_json(show: &Show) -> Result<app::AppInit, glib::Error> {
+    serde_json::from_slice(&show.args).map_err(|why| {
+        glib::Error::new(
+            gio::DBusError::InvalidArgs,
+            &format!(
+                "The arguments are from a different version of anyrun, restart the daemon: {why}"
+            ),
+        )
+    })
+}
+
 /// A wayland native, highly customizable runner.
+///
+/// Sent to the daemon, fields added later default when missing so that older clients keep working.
 #[derive(Parser, Clone, Debug, Serialize, Deserialize)]
 #[command(version, about)]
 pub struct Args {
     /// Override the path to the config directory
     #[arg(short, long)]
     config_dir: Option<String>,
+    /// Use the overrides of a profile defined in the config
+    #[arg(long)]
+    profile: Option<String>,
+    /// Load only this plugin instead of the configured ones, can be repeated
+    // `--plugins` is what the generated argument of the `plugins` option used to be called
+    #[arg(long, alias = "plugins")]
+    #[serde(default)]
+    plugin: Vec<PathBuf>,
+    /// Load this plugin in addition to the configured ones, can be repeated
+    #[arg(long)]
+    #[serde(default)]
+    add_plugin: Vec<PathBuf>,
+    /// Fill the input with this text when shown
+    #[arg(long)]
+    query: Option<String>,
+    /// Select the text given with `--query`, so that typing replaces it
+    #[arg(long, requires = "query")]
+    #[serde(default)]
+    select_all: bool,
+    /// Act as a dmenu replacement: only the stdin plugin is loaded, the input is printed if no line
+    /// matches it and the exit code is 1 if nothing is selected
+    #[arg(long)]
+    #[serde(default)]
+    dmenu: bool,
+    /// Text shown in front of the input
+    #[arg(long)]
+    prompt: Option<String>,
+    /// Text shown in the input while it is empty
+    #[arg(long)]
+    placeholder: Option<String>,
+    /// Mask the input, and don't remember it in the history
+    #[arg(long)]
+    #[serde(default)]
+    password: bool,
+    /// How the result is printed when the launcher is closed
+    #[arg(long, value_enum, default_value_t = OutputFormat::Plain)]
+    #[serde(default)]
+    output: OutputFormat,
     #[command(flatten)]
     config: ConfigArgs,
 
@@ -89,22 +257,192 @@ pub struct Args {
     command: Option<Command>,
 }
 
-#[derive(Subcommand, Debug, Clone, Copy, Deserialize, Serialize)]
+/// The format of the result printed when the launcher is closed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum OutputFormat {
+    /// Print what the selected plugin outputs, if anything
+    #[default]
+    Plain,
+    /// Print a JSON object describing the selection and the final input
+    Json,
+}
+
+impl Args {
+    /// Apply the selected profile and the overrides from the command line to the config,
+    /// returns `false` if the profile doesn't exist
+    pub fn apply_overrides(&self, config: &mut Config) -> bool {
+        let profile_exists = self
+            .profile
+            .as_ref()
+            .is_none_or(|profile| config.apply_profile(profile));
+
+        config.merge_opt(self.config.clone());
+
+        if !self.plugin.is_empty() {
+            config.plugins = self.plugin.clone();
+        }
+        config.plugins.extend(self.add_plugin.iter().cloned());
+
+        if self.dmenu {
+            config.plugins = vec!["libstdin.so".into()];
+        }
+
+        profile_exists
+    }
+}
+
+#[derive(Subcommand, Debug, Clone, Deserialize, Serialize)]
 enum Command {
     Daemon,
     Close,
+    /// Close the launcher if it is visible, otherwise show it
+    Toggle,
     Quit,
+    ClearHistory,
+    CheckConfig,
+    /// Print the signals of the daemon as JSON lines
+    Monitor,
+    /// Replace the input of the visible launcher
+    SetQuery {
+        text: String,
+    },
+    /// Perform an action on the visible launcher, named like in the keybinds, for example
+    /// `Down` or `SelectIndex(1)`
+    Action {
+        name: String,
+    },
+    /// Print the input and the visible matches of the launcher as JSON
+    GetState,
+}
+
+/// A call waiting for the result of the launcher it showed
+#[derive(Clone)]
+pub enum Invocation {
+    /// `Run` or `Toggle`, answered with the JSON encoded `RunResult`
+    Json(gio::DBusMethodInvocation),
+    /// `Show`, answered with the JSON encoded `PostRunAction` like before `RunResult` existed
+    Legacy(gio::DBusMethodInvocation),
+    /// `Open`, answered with the result as its output arguments
+    Typed(gio::DBusMethodInvocation),
+}
+
+impl Invocation {
+    pub fn return_result(self, result: &app::RunResult) {
+        match self {
+            Self::Json(invocation) => invocation.return_value(Some(
+                &(serde_json::to_vec(&Ok::<_, app::ShowError>(result)).unwrap(),).to_variant(),
+            )),
+            Self::Legacy(invocation) => invocation.return_value(Some(
+                &(serde_json::to_vec(&Ok::<_, app::ShowError>(result.legacy_action())).unwrap(),)
+                    .to_variant(),
+            )),
+            Self::Typed(invocation) => invocation.return_value(Some(&result.to_variant())),
+        }
+    }
+}
+
+/// Exit with an error about a reply of the daemon that couldn't be understood, which happens
+/// when the daemon started before an upgrade is still running
+fn daemon_mismatch(why: impl std::fmt::Display) -> ! {
+    eprintln!(
+        "[anyrun] The daemon is a different version of anyrun ({why}), restart it with \
+         `anyrun quit` and `anyrun daemon`"
+    );
+    std::process::exit(1);
+}
+
+/// Decode the JSON encoded reply of a method of the daemon
+fn decode_reply<T: serde::de::DeserializeOwned>(res: &glib::Variant) -> T {
+    let (bytes,) = res
+        .get::<(Vec<u8>,)>()
+        .unwrap_or_else(|| daemon_mismatch("unexpected reply type"));
+    serde_json::from_slice(&bytes).unwrap_or_else(|why| daemon_mismatch(why))
+}
+
+/// Exit with the error of a failed call to the daemon
+fn call_failed(mut why: glib::Error) -> ! {
+    let unknown = why.matches(gio::DBusError::UnknownMethod);
+    gio::DBusError::strip_remote_error(&mut why);
+    if unknown {
+        daemon_mismatch(why);
+    }
+    eprintln!("[anyrun] {why}");
+    std::process::exit(1);
+}
+
+/// Call a typed method on the daemon, exiting with an error if it fails
+fn call_daemon(
+    app: &gtk::Application,
+    proxy: &gio::DBusProxy,
+    method: &str,
+    parameters: Option<&glib::Variant>,
+) -> glib::Variant {
+    if !app.is_remote() {
+        eprintln!("[anyrun] The daemon isn't running");
+        std::process::exit(1);
+    }
+
+    proxy
+        .call_sync(
+            method,
+            parameters,
+            gio::DBusCallFlags::NONE,
+            1000,
+            Option::<&gio::Cancellable>::None,
+        )
+        .unwrap_or_else(|why| call_failed(why))
 }
 
 /// Refcelled state for the daemon DBus listener
 pub struct DaemonState {
     sender: Option<Sender<app::AppMsg>>,
+    connection: gio::DBusConnection,
+    /// Kept running between shows, so that plugins don't need to be initialized every time
+    provider: Option<provider::Provider>,
+}
+
+impl DaemonState {
+    /// Emit a signal of the interface from the daemon's object
+    pub fn emit_signal(&self, name: &str, parameters: Option<&glib::Variant>) {
+        if let Err(why) = self.connection.emit_signal(
+            None,
+            "/org/anyrun/anyrun",
+            "org.anyrun.Anyrun",
+            name,
+            parameters,
+        ) {
+            eprintln!("[anyrun] Failed to emit the `{name}` signal: {why}");
+        }
+    }
 }
 
 fn main() {
     let args = Args::parse();
+
+    // Commands that don't use the daemon run before registering, so that they work without a
+    // session bus and don't take the name of the application
+    match args.command {
+        Some(Command::ClearHistory) => {
+            if let Err(why) = history::History::clear() {
+                eprintln!("[anyrun] Failed to clear history: {why}");
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::CheckConfig) => {
+            if !check::check_config(&args) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        _ => (),
+    }
+
     let flags = if matches!(args.command, Some(Command::Daemon)) {
         gio::ApplicationFlags::IS_SERVICE
+    } else if matches!(args.command, Some(Command::Monitor)) {
+        // Don't take the name, which would keep the daemon from starting
+        gio::ApplicationFlags::NON_UNIQUE
     } else {
         Default::default()
     };
@@ -130,7 +468,7 @@ fn main() {
     .unwrap();
 
     match args.command {
-        None => {
+        None | Some(Command::Toggle) => {
             let stdin = if io::stdin().is_terminal() {
                 Vec::new()
             } else {
@@ -139,11 +477,18 @@ fn main() {
                 buf
             };
             let env = std::env::vars().collect();
+            let dmenu = args.dmenu;
+            let output = args.output;
+            let method = if matches!(args.command, Some(Command::Toggle)) {
+                "Toggle"
+            } else {
+                "Run"
+            };
 
             if app.is_remote() {
                 let res = proxy
                     .call_sync(
-                        "Show",
+                        method,
                         Some(
                             &(serde_json::to_vec(&app::AppInit { args, stdin, env }).unwrap(),)
                                 .to_variant(),
@@ -152,18 +497,17 @@ fn main() {
                         1_000_000_000, // Very long timeout to get results from the daemon
                         Option::<&gio::Cancellable>::None,
                     )
-                    .unwrap();
+                    .unwrap_or_else(|why| call_failed(why));
 
-                let (bytes,): (Vec<u8>,) = FromVariant::from_variant(&res).unwrap();
-
-                let res =
-                    serde_json::from_slice::<Result<app::PostRunAction, app::ShowError>>(&bytes)
-                        .unwrap();
-                match res {
-                    Ok(app::PostRunAction::Stdout(stdout)) => {
-                        io::stdout().lock().write_all(&stdout).unwrap()
+                // `Toggle` returns `None` if it closed the launcher instead of showing it
+                match decode_reply::<Result<Option<app::RunResult>, app::ShowError>>(&res) {
+                    Ok(None) => (),
+                    Ok(Some(result)) => {
+                        result.print(output);
+                        if dmenu && result.cancelled() {
+                            std::process::exit(1);
+                        }
                     }
-                    Ok(app::PostRunAction::None) => (),
                     Err(app::ShowError::AlreadyShowed) => {
                         eprintln!("[anyrun] Anyrun is already visible.");
                         std::process::exit(1);
@@ -201,11 +545,9 @@ fn main() {
                     100,
                     Option::<&gio::Cancellable>::None,
                 )
-                .unwrap();
+                .unwrap_or_else(|why| call_failed(why));
 
-            let (bytes,): (Vec<u8>,) = FromVariant::from_variant(&res).unwrap();
-            let res = serde_json::from_slice(&bytes).unwrap();
-            match res {
+            match decode_reply(&res) {
                 Ok(()) => {}
                 Err(CloseError::NotShowed) => {
                     eprintln!("[anyrun] Anyrun isn't currently visible");
@@ -232,10 +574,74 @@ fn main() {
                 .unwrap();
             app.run_with_args(&Vec::<String>::new());
         }
+        Some(Command::ClearHistory | Command::CheckConfig) => {
+            unreachable!("handled before registering")
+        }
+        Some(Command::SetQuery { text }) => {
+            call_daemon(&app, &proxy, "SetQuery", Some(&(text,).to_variant()));
+        }
+        Some(Command::Action { name }) => {
+            call_daemon(&app, &proxy, "Action", Some(&(name,).to_variant()));
+        }
+        Some(Command::GetState) => {
+            let res = call_daemon(&app, &proxy, "GetState", None);
+            let (query, matches): (String, Vec<(String, String, String, bool)>) = res
+                .get()
+                .unwrap_or_else(|| daemon_mismatch("unexpected reply type"));
+
+            println!(
+                "{}",
+                serde_json::json!({
+                    "query": query,
+                    "matches": matches
+                        .into_iter()
+                        .map(|(plugin, title, description, selected)| serde_json::json!({
+                            "plugin": plugin,
+                            "title": title,
+                            "description": Some(description).filter(|desc| !desc.is_empty()),
+                            "selected": selected,
+                        }))
+                        .collect::<Vec<_>>(),
+                })
+            );
+        }
+        Some(Command::Monitor) => {
+            let _subscription = dbus_conn.subscribe_to_signal(
+                None,
+                Some("org.anyrun.Anyrun"),
+                None,
+                Some("/org/anyrun/anyrun"),
+                None,
+                gio::DBusSignalFlags::NONE,
+                |signal| {
+                    let line = match signal.signal_name {
+                        "Activated" => {
+                            let Some((plugin, title)) = signal.parameters.get::<(String, String)>()
+                            else {
+                                return;
+                            };
+                            serde_json::json!({
+                                "signal": "Activated",
+                                "plugin": plugin,
+                                "title": title,
+                            })
+                        }
+                        name => serde_json::json!({ "signal": name }),
+                    };
+                    println!("{line}");
+                },
+            );
+
+            glib::MainLoop::new(None, false).run();
+        }
         Some(Command::Daemon) => {
             let _hold_guard = app.hold();
 
-            let state = Rc::new(RefCell::new(DaemonState { sender: None }));
+            let state = Rc::new(RefCell::new(DaemonState {
+                sender: None,
+                connection: dbus_conn.clone(),
+                provider: None,
+            }));
 
             dbus_conn
                 .register_object("/org/anyrun/anyrun", &interface)
@@ -248,23 +654,86 @@ fn main() {
                     move |_conn, _sender, method, invocation| {
                         let app = app.unwrap();
                         match method {
-                            InterfaceMethod::Show(show) => {
-                                // Only launch an instance if another one doesn't exist
-                                if state.borrow().sender.is_none() {
-                                    state.borrow_mut().sender = Some(app::App::launch(
-                                        &app,
-                                        serde_json::from_slice(&show.args).unwrap(),
-                                        Some((state.clone(), invocation)),
-                                    ));
+                            InterfaceMethod::Open(options) => {
+                                if state.borrow().sender.is_some() {
+                                    invocation.return_dbus_error(
+                                        ERROR_ALREADY_VISIBLE,
+                                        "Anyrun is already visible",
+                                    );
+                                    return;
+                                }
+                                match app_init_from_options(&options) {
+                                    Ok(app_init) => {
+                                        state.borrow_mut().sender = Some(app::App::launch(
+                                            &app,
+                                            app_init,
+                                            Some((state.clone(), Invocation::Typed(invocation))),
+                                        ));
+                                    }
+                                    Err(why) => invocation.return_gerror(why),
+                                }
+                            }
+                            InterfaceMethod::Hide => {
+                                if let Some(sender) = state.borrow().sender.clone() {
+                                    sender.emit(app::AppMsg::Action(config::Action::Close));
+                                    invocation.return_value(None);
                                 } else {
+                                    invocation.return_dbus_error(
+                                        ERROR_NOT_VISIBLE,
+                                        "Anyrun isn't currently visible",
+                                    );
+                                }
+                            }
+                            InterfaceMethod::SetQuery(_)
+                            | InterfaceMethod::Action(_)
+                            | InterfaceMethod::GetState => {
+                                let Some(sender) = state.borrow().sender.clone() else {
+                                    invocation.return_dbus_error(
+                                        ERROR_NOT_VISIBLE,
+                                        "Anyrun isn't currently visible",
+                                    );
+                                    return;
+                                };
+                                match method {
+                                    InterfaceMethod::SetQuery(text) => {
+                                        sender.emit(app::AppMsg::SetQuery(text));
+                                        invocation.return_value(None);
+                                    }
+                                    // Answered by the launcher
+                                    InterfaceMethod::Action(action) => sender
+                                        .emit(app::AppMsg::RemoteAction { action, invocation }),
+                                    _ => sender.emit(app::AppMsg::GetState(invocation)),
+                                }
+                            }
+                            InterfaceMethod::Run(ref show) | InterfaceMethod::Show(ref show) => {
+                                // Only launch an instance if another one doesn't exist
+                                if state.borrow().sender.is_some() {
                                     invocation.return_value(Some(
-                                        &(serde_json::to_vec(&Err::<app::PostRunAction, _>(
+                                        &(serde_json::to_vec(&Err::<app::RunResult, _>(
                                             app::ShowError::AlreadyShowed,
                                         ))
                                         .unwrap(),)
                                             .to_variant(),
                                     ));
+                                    return;
                                 }
+                                let app_init = match app_init_from_json(show) {
+                                    Ok(app_init) => app_init,
+                                    Err(why) => {
+                                        invocation.return_gerror(why);
+                                        return;
+                                    }
+                                };
+                                let invocation = if matches!(method, InterfaceMethod::Show(_)) {
+                                    Invocation::Legacy(invocation)
+                                } else {
+                                    Invocation::Json(invocation)
+                                };
+                                state.borrow_mut().sender = Some(app::App::launch(
+                                    &app,
+                                    app_init,
+                                    Some((state.clone(), invocation)),
+                                ));
                             }
                             InterfaceMethod::Close => {
                                 // If launcher is open, return an ok value. If launcher is closed, return an err to
@@ -283,6 +752,30 @@ fn main() {
                                     ))
                                 }
                             }
+                            InterfaceMethod::Toggle(show) => {
+                                let sender = state.borrow().sender.clone();
+                                if let Some(sender) = sender {
+                                    sender.emit(app::AppMsg::Action(config::Action::Close));
+                                    invocation.return_value(Some(
+                                        &(serde_json::to_vec(&Ok::<_, app::ShowError>(
+                                            Option::<app::RunResult>::None,
+                                        ))
+                                        .unwrap(),)
+                                            .to_variant(),
+                                    ));
+                                } else {
+                                    match app_init_from_json(&show) {
+                                        Ok(app_init) => {
+                                            state.borrow_mut().sender = Some(app::App::launch(
+                                                &app,
+                                                app_init,
+                                                Some((state.clone(), Invocation::Json(invocation))),
+                                            ));
+                                        }
+                                        Err(why) => invocation.return_gerror(why),
+                                    }
+                                }
+                            }
                             InterfaceMethod::Quit => {
                                 invocation.return_value(None);
                                 app.quit();
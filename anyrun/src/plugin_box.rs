@@ -10,13 +10,19 @@ use crate::Config;
 
 pub struct PluginMatch {
     pub content: Match,
+    /// The plugin weight divided by the rank of the match within its plugin, starting at 1.
+    /// Plugins don't report how relevant their matches are, so matches of the same rank have the
+    /// same score before weighting.
+    pub score: f64,
     pub row: gtk::ListBoxRow,
+    /// Shows the number used to quick select the match
+    pub badge: gtk::Label,
     config: Arc<Config>,
 }
 
 #[relm4::factory(pub)]
 impl FactoryComponent for PluginMatch {
-    type Init = (Match, Arc<Config>);
+    type Init = (Match, f64, Arc<Config>);
     type Input = ();
     type Output = ();
     type CommandOutput = ();
@@ -67,6 +73,13 @@ impl FactoryComponent for PluginMatch {
                         set_halign: gtk::Align::Start,
                         set_valign: gtk::Align::Center,
                     }
+                },
+
+                #[name = "badge"]
+                gtk::Label {
+                    set_css_classes: &["match", "index"],
+                    set_valign: gtk::Align::Center,
+                    set_visible: false,
                 }
             }
         }
@@ -82,6 +95,7 @@ impl FactoryComponent for PluginMatch {
         let widgets = view_output!();
 
         self.row = root;
+        self.badge = widgets.badge.clone();
 
         if !self.config.hide_icons {
             if let ROption::RSome(icon) = &self.content.icon {
@@ -104,7 +118,7 @@ impl FactoryComponent for PluginMatch {
     }
 
     fn init_model(
-        (content, config): Self::Init,
+        (content, score, config): Self::Init,
         _index: &Self::Index,
         _sender: FactorySender<Self>,
     ) -> Self {
@@ -112,7 +126,9 @@ impl FactoryComponent for PluginMatch {
 
         Self {
             row,
+            badge: gtk::Label::default(),
             content,
+            score,
             config,
         }
     }
@@ -124,6 +140,12 @@ pub struct PluginBox {
     config: Arc<Config>,
     visible: bool,
     enabled: bool,
+    /// Whether a query has been sent that the plugin has not answered yet
+    loading: bool,
+    /// Whether the plugin has been loading for long enough that it should be shown
+    slow: bool,
+    /// Whether the plugin did not answer the last query within its timeout
+    timed_out: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -133,6 +155,13 @@ pub enum PluginBoxInput {
     /// Sent when there is a possibility that the plugin may need to hide, aka
     /// all its matches have already been hidden
     MaybeHide,
+    /// Sent when a query has been sent to the plugin
+    Loading,
+    /// Sent when the plugin has not answered a query for a while, which shows the plugin
+    /// even if it has no matches yet
+    Slow,
+    /// Sent when the plugin has not answered a query within its timeout
+    TimedOut,
 }
 
 #[derive(Debug)]
@@ -154,6 +183,8 @@ impl FactoryComponent for PluginBox {
             #[watch]
             set_visible: self.visible,
             set_css_classes: &["plugin"],
+            #[watch]
+            set_class_active: ("loading", self.loading),
 
             gtk::Box {
                 set_visible: !self.config.hide_plugin_info,
@@ -177,10 +208,28 @@ impl FactoryComponent for PluginBox {
                         set_label: &self.plugin_info.name,
                         set_halign: gtk::Align::Start,
                         set_valign: gtk::Align::Center,
+                    },
+                    gtk::Spinner {
+                        set_css_classes: &["plugin", "info"],
+                        set_valign: gtk::Align::Center,
+                        #[watch]
+                        set_spinning: self.loading,
+                        #[watch]
+                        set_visible: self.loading,
                     }
                 }
             },
 
+            // The spinner of the info box is hidden along with it
+            gtk::Spinner {
+                set_css_classes: &["plugin"],
+                set_valign: gtk::Align::Start,
+                #[watch]
+                set_spinning: self.loading,
+                #[watch]
+                set_visible: self.config.hide_plugin_info && self.loading,
+            },
+
             #[local_ref]
             matches -> gtk::ListBox {
                 set_css_classes: &["plugin"],
@@ -190,6 +239,15 @@ impl FactoryComponent for PluginBox {
                         sender.output(PluginBoxOutput::RowSelected(index.clone())).unwrap();
                     }
                 }
+            },
+
+            gtk::Label {
+                set_css_classes: &["plugin", "error"],
+                set_label: "The plugin did not respond in time",
+                set_halign: gtk::Align::Start,
+                set_hexpand: true,
+                #[watch]
+                set_visible: self.timed_out,
             }
         }
     }
@@ -223,6 +281,9 @@ impl FactoryComponent for PluginBox {
             config,
             visible: false,
             enabled: true,
+            loading: false,
+            slow: false,
+            timed_out: false,
         }
     }
 
@@ -239,13 +300,20 @@ impl FactoryComponent for PluginBox {
                 }
 
                 self.visible = !matches.is_empty();
+                self.loading = false;
+                self.slow = false;
+                self.timed_out = false;
                 {
                     let mut guard = self.matches.guard();
 
                     guard.clear();
 
-                    for _match in matches {
-                        guard.push_back((_match, self.config.clone()));
+                    // Plugins return their matches sorted by relevance but without a score, so
+                    // only the rank is known. How plugins compare is left to their weights.
+                    let weight = self.config.plugin_weight(&self.plugin_info.name);
+                    for (i, _match) in matches.into_iter().enumerate() {
+                        let score = weight / (i + 1) as f64;
+                        guard.push_back((_match, score, self.config.clone()));
                     }
                 }
                 sender.output(PluginBoxOutput::MatchesLoaded).unwrap();
@@ -268,7 +336,22 @@ impl FactoryComponent for PluginBox {
                     }
                 }
 
-                self.visible = !hide;
+                self.visible = !hide || self.slow || self.timed_out;
+            }
+            PluginBoxInput::Loading => {
+                self.loading = self.enabled;
+            }
+            PluginBoxInput::Slow => {
+                if self.enabled && self.loading {
+                    self.slow = true;
+                    self.visible = true;
+                }
+            }
+            PluginBoxInput::TimedOut => {
+                if self.enabled {
+                    self.timed_out = true;
+                    self.visible = true;
+                }
             }
         }
 
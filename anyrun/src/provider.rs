@@ -2,21 +2,154 @@ use std::{
     env, fs,
     io::{self, Write},
     path::PathBuf,
-    process::{Command, Stdio},
-    sync::Arc,
+    process::{self, Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::SystemTime,
 };
 
 use anyrun_provider_ipc as ipc;
 use relm4::Sender;
-use tokio::{net::UnixListener, sync::mpsc::Receiver};
+use tokio::{
+    net::UnixListener,
+    sync::mpsc::{self, Receiver, UnboundedReceiver, UnboundedSender},
+};
 
 use crate::config::Config;
 
+/// The amount of workers started, to give each one its own socket
+static WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether a variable of the launching environment is compared to decide if a running provider can
+/// be reused. Variables that change with the shell, like `PWD` or `SHLVL`, would otherwise restart
+/// it on every launch from a terminal.
+fn env_affects_provider(name: &str) -> bool {
+    matches!(
+        name,
+        "PATH"
+            | "HOME"
+            | "USER"
+            | "SHELL"
+            | "LANG"
+            | "LANGUAGE"
+            | "WAYLAND_DISPLAY"
+            | "DISPLAY"
+            | "DBUS_SESSION_BUS_ADDRESS"
+    ) || name.starts_with("LC_")
+        || name.starts_with("XDG_")
+}
+
+/// A launcher using the provider, which receives the responses until it sends `Request::Quit`
+pub struct Session {
+    pub rx: Receiver<ipc::Request>,
+    pub sender: Sender<ipc::Response>,
+}
+
+/// What a provider was started with, a running one is only reused if this is unchanged
+#[derive(PartialEq)]
+struct ProviderKey {
+    provider: PathBuf,
+    plugins: Vec<PathBuf>,
+    config_dir: Option<String>,
+    /// The variables of the environment that matter to plugins, sorted by name
+    env: Vec<(String, String)>,
+    /// The last modification in the config directory, as plugins read their config on init
+    modified: Option<SystemTime>,
+}
+
+impl ProviderKey {
+    fn new(config: &Config, config_dir: &Option<String>, env: &[(String, String)]) -> Self {
+        let modified = config_dir.as_ref().and_then(|config_dir| {
+            let entries = fs::read_dir(config_dir).ok()?;
+            entries
+                .flatten()
+                .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+                .chain(fs::metadata(config_dir).ok()?.modified().ok())
+                .max()
+        });
+
+        let mut env = env
+            .iter()
+            .filter(|(name, _)| env_affects_provider(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        env.sort();
+
+        Self {
+            provider: config.provider.clone(),
+            plugins: config.plugins.clone(),
+            config_dir: config_dir.clone(),
+            env,
+            modified,
+        }
+    }
+}
+
+/// A provider kept running between shows by the daemon, so plugins are only initialized once.
+/// It keeps the environment of the show that started it. Dropping it makes the provider quit once
+/// its current session ends.
+pub struct Provider {
+    key: ProviderKey,
+    sessions: UnboundedSender<Session>,
+}
+
+impl Provider {
+    fn spawn(
+        key: ProviderKey,
+        config: Arc<Config>,
+        config_dir: Option<String>,
+        env: Vec<(String, String)>,
+    ) -> Self {
+        let (sessions_tx, sessions) = mpsc::unbounded_channel();
+        thread::spawn(move || {
+            if let Err(why) = worker(config, config_dir, sessions, Vec::new(), env) {
+                eprintln!("[anyrun] IPC worker returned an error: {why}");
+            }
+        });
+
+        Self {
+            key,
+            sessions: sessions_tx,
+        }
+    }
+
+    /// Start a session on the running provider, which is restarted if it was started with a
+    /// different config or has exited
+    pub fn attach(
+        provider: &mut Option<Provider>,
+        config: Arc<Config>,
+        config_dir: Option<String>,
+        env: Vec<(String, String)>,
+        session: Session,
+    ) {
+        let key = ProviderKey::new(&config, &config_dir, &env);
+        // Dropping the previous provider makes it quit once its current session ends
+        let session = match provider.take().filter(|provider| provider.key == key) {
+            Some(running) => match running.sessions.send(session) {
+                Ok(()) => {
+                    *provider = Some(running);
+                    return;
+                }
+                Err(mpsc::error::SendError(session)) => session,
+            },
+            None => session,
+        };
+
+        let spawned = Self::spawn(key, config, config_dir, env);
+        let _ = spawned.sessions.send(session);
+        *provider = Some(spawned);
+    }
+}
+
+/// Run the provider and serve the sessions received on `sessions` one after another, until the
+/// sender is dropped
 pub fn worker(
     config: Arc<Config>,
     config_dir: Option<String>,
-    mut rx: Receiver<anyrun_provider_ipc::Request>,
-    sender: Sender<anyrun_provider_ipc::Response>,
+    mut sessions: UnboundedReceiver<Session>,
     // The stdin received by the launching command
     stdin: Vec<u8>,
     // The environment of the launching command
@@ -27,9 +160,12 @@ pub fn worker(
         .build()
         .unwrap()
         .block_on(async {
+            // The daemon can have a provider quitting while another one starts
             let socket_path = format!(
-                "{}/anyrun.sock",
-                env::var("XDG_RUNTIME_DIR").unwrap_or("/tmp".to_string())
+                "{}/anyrun-{}-{}.sock",
+                env::var("XDG_RUNTIME_DIR").unwrap_or("/tmp".to_string()),
+                process::id(),
+                WORKERS.fetch_add(1, Ordering::Relaxed)
             );
             // Make sure that it does not exist already
             let _ = fs::remove_file(&socket_path);
@@ -68,28 +204,56 @@ pub fn worker(
             let (stream, _) = listener.accept().await?;
             let mut socket = ipc::Socket::new(stream);
 
-            loop {
-                tokio::select! {
-                    req = rx.recv() => {
-                        if let Some(req) = req {
-                        socket.send(&req).await?;
-                        if matches!(req, ipc::Request::Quit) {
+            // Every session needs the plugin info, which is only sent once the plugins are initialized
+            let info = match socket.recv().await {
+                Ok(ipc::Response::Ready { info }) => info,
+                Ok(_) => {
+                    eprintln!("[anyrun] Provider did not send its plugins first");
+                    return Ok(());
+                }
+                Err(why) => {
+                    eprintln!("[anyrun] Error reading from IPC: {why}");
+                    return Ok(());
+                }
+            };
+
+            'sessions: loop {
+                let mut session = tokio::select! {
+                    session = sessions.recv() => match session {
+                        Some(session) => session,
+                        None => break,
+                    },
+                    // A launcher that closes while responses are on their way drops the provider,
+                    // so there should be nothing to discard here
+                    res = socket.recv() => match res {
+                        Ok(_) => continue,
+                        Err(why) => {
+                            eprintln!("[anyrun] Error reading from IPC: {why}");
                             break;
                         }
-                        }
-                    }
-                    res = socket.recv() => {
-                        match res {
-                    Ok(response) => sender.emit(response),
-                    Err(why) => {
-                        eprintln!("[anyrun] Error reading from IPC: {why}");
-                        break;
                     },
-                        }
+                };
+
+                session.sender.emit(ipc::Response::Ready { info: info.clone() });
+
+                loop {
+                    tokio::select! {
+                        req = session.rx.recv() => match req {
+                            Some(ipc::Request::Quit) | None => break,
+                            Some(req) => socket.send(&req).await?,
+                        },
+                        res = socket.recv() => match res {
+                            Ok(response) => session.sender.emit(response),
+                            Err(why) => {
+                                eprintln!("[anyrun] Error reading from IPC: {why}");
+                                break 'sessions;
+                            }
+                        },
                     }
                 }
             }
 
+            let _ = socket.send(&ipc::Request::Quit).await;
             // Remove it after we are done with it
             let _ = fs::remove_file(&socket_path);
             // Make sure it exits properly and doesn't leave a zombie process
@@ -166,6 +166,24 @@ in
           '';
         };
 
+        position = mkOption {
+          type = enum [
+            "top"
+            "bottom"
+            "center"
+            "topLeft"
+            "topRight"
+            "bottomLeft"
+            "bottomRight"
+          ];
+          default = "top";
+          description = ''
+            Which part of the screen the runner is placed relative to. With `top` and `bottom`, x positions the
+            center of the runner and y is the distance from that edge. `center` ignores x and y, and the corners
+            use x and y as the distances from the corner.
+          '';
+        };
+
         hideIcons = mkOption {
           type = bool;
           default = false;
@@ -189,6 +207,16 @@ in
           description = "Layer shell layer (background, bottom, top or overlay)";
         };
 
+        monitor = mkOption {
+          type = nullOr (either int str);
+          default = null;
+          example = "DP-1";
+          description = ''
+            The monitor to show the runner on, either an index starting from 0 or a connector name.
+            When null, the compositor chooses, which is usually the focused monitor.
+          '';
+        };
+
         keyboardMode = mkOption {
           type = enum [
             "exclusive"
@@ -198,12 +226,27 @@ in
           description = "Layer shell keyboard mode";
         };
 
+        layout = mkOption {
+          type = enum [
+            "grouped"
+            "merged"
+          ];
+          default = "grouped";
+          description = "Whether matches are grouped by plugin, or merged into a single list ordered by the plugin weight divided by the rank within the plugin";
+        };
+
         hidePluginInfo = mkOption {
           type = bool;
           default = false;
           description = "Hide the plugin info panel";
         };
 
+        hideIndexBadges = mkOption {
+          type = bool;
+          default = false;
+          description = "Hide the numbers shown next to the first nine matches for quick selection";
+        };
+
         closeOnClick = mkOption {
           type = bool;
           default = false;
@@ -222,6 +265,36 @@ in
           description = "Limit amount of entries shown in total";
         };
 
+        queryDebounce = mkOption {
+          type = int;
+          default = 0;
+          description = "Time in milliseconds to wait after the input stops changing before querying the plugins";
+        };
+
+        pluginTimeout = mkOption {
+          type = int;
+          default = 5000;
+          description = "Time in milliseconds a plugin has to answer a query before a timeout message is shown, 0 disables it. A new input is only queried once every plugin has answered or timed out";
+        };
+
+        frecency = mkOption {
+          type = bool;
+          default = false;
+          description = "Remember selected matches, and rank frequently and recently selected ones higher";
+        };
+
+        frecencyHalfLife = mkOption {
+          type = float;
+          default = 72.0;
+          description = "The time in hours after which the weight of a past selection is halved";
+        };
+
+        maxQueryHistory = mkOption {
+          type = int;
+          default = 0;
+          description = "How many submitted inputs are remembered for the query history, 0 disables it";
+        };
+
         keybinds = mkOption {
           type = nullOr (
             listOf (submodule {
@@ -248,6 +321,18 @@ in
                     "select"
                     "up"
                     "down"
+                    "pageUp"
+                    "pageDown"
+                    "first"
+                    "last"
+                    "nextPlugin"
+                    "prevPlugin"
+                    "complete"
+                    "historyPrev"
+                    "historyNext"
+                    "toggleMark"
+                    "selectMarked"
+                    "actionMenu"
                   ];
                 };
               };
@@ -309,6 +394,9 @@ in
       capitalize =
         string: toUpper (substring 0 1 string) + toLower (substring 1 ((stringLength string) - 1) string);
 
+      # Like capitalize, but keeps the case of the rest of the string, for camelCase values
+      upperFirst = string: toUpper (substring 0 1 string) + substring 1 ((stringLength string) - 1) string;
+
       parsedPlugins =
         if cfg.config.plugins == null then
           [ ]
@@ -347,7 +435,7 @@ in
                     ${optionalString x.ctrl "ctrl: true,"}
                     ${optionalString x.alt "alt: true,"}
                     key: "${x.key}",
-                    action: ${capitalize x.action},
+                    action: ${upperFirst x.action},
                   ),
                 '') cfg.config.keybinds
               }],
@@ -423,13 +511,29 @@ in
               y: ${stringifyNumeric cfg.config.y},
               width: ${stringifyNumeric cfg.config.width},
               height: ${stringifyNumeric cfg.config.height},
+              position: ${upperFirst cfg.config.position},
               hide_icons: ${boolToString cfg.config.hideIcons},
               ignore_exclusive_zones: ${boolToString cfg.config.ignoreExclusiveZones},
               layer: ${capitalize cfg.config.layer},
               keyboard_mode: ${keyboardMode},
+              layout: ${capitalize cfg.config.layout},
+              ${
+                if cfg.config.monitor == null then
+                  ""
+                else if builtins.isInt cfg.config.monitor then
+                  "monitor: Index(${toString cfg.config.monitor}),"
+                else
+                  "monitor: Connector(${toJSON cfg.config.monitor}),"
+              }
               hide_plugin_info: ${boolToString cfg.config.hidePluginInfo},
+              hide_index_badges: ${boolToString cfg.config.hideIndexBadges},
               close_on_click: ${boolToString cfg.config.closeOnClick},
               show_results_immediately: ${boolToString cfg.config.showResultsImmediately},
+              query_debounce: ${toString cfg.config.queryDebounce},
+              plugin_timeout: ${toString cfg.config.pluginTimeout},
+              frecency: ${boolToString cfg.config.frecency},
+              frecency_half_life: ${toString cfg.config.frecencyHalfLife},
+              max_query_history: ${toString cfg.config.maxQueryHistory},
               max_entries: ${
                 if cfg.config.maxEntries == null then "None" else "Some(${toString cfg.config.maxEntries})"
               },